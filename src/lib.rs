@@ -35,6 +35,12 @@
 //!
 
 extern crate unicode_width;
+use unicode_width::UnicodeWidthStr;
+
+extern crate unicode_segmentation;
+use unicode_segmentation::UnicodeSegmentation;
+
+extern crate libc;
 
 extern crate getopts;
 use getopts::Options;
@@ -48,17 +54,138 @@ use regex::Regex;
 extern crate dict;
 use dict::{ Dict, DictIface };
 
+#[cfg(feature = "parquet")]
+extern crate parquet;
+
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+
+#[cfg(feature = "gzip")]
+extern crate flate2;
+
+#[cfg(feature = "zstd")]
+extern crate zstd;
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+extern crate io_uring;
+
 use std::io;
+use std::io::{Read, Write};
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::fs;
 #[cfg(target_os = "linux")]
 use std::os::linux::fs::MetadataExt;
 #[cfg(target_os = "macos")]
 use std::os::unix::fs::MetadataExt;
+use std::os::unix::ffi::OsStrExt;
 use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::cell::RefCell;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+use std::thread;
+use std::collections::{HashMap, HashSet};
 
 const VERSTR    : &str = "v0.2.0";
 const DEF_WIDTH : u16  = 80;
+const SNAPSHOT  : &[u8]= b"DUTREE\x06"; // magic and format version
+const CACHE_MAGIC : &[u8] = b"DUCACHE\x04";
+const SAMPLE_SIZE : usize = 256;    // entries looked at in larger directories, with --estimate
+const SPARSE_HOLES : u64 = 64 * 1024; // unallocated bytes for --sparse to tag a file
+// the options that pick a report format instead of the tree, the last one is --du
+const FORMATS   : &[&str] = &[ "csv", "tsv", "dot", "ncdu", "json", "format", "print0", "list", "summarize",
+                               "by-type", "by-category", "age-histogram", "size-histogram", "stats", "by-mount",
+                               "duplicates", "biggest-dirs", "du" ];
+const SVG_WIDTH : f64  = 1280.0;
+const SVG_HEIGHT: f64  = 800.0;
+
+// what happened during the scan, for the footer
+static ERRORS     : AtomicUsize = AtomicUsize::new( 0 );
+static EXCLUDED   : AtomicUsize = AtomicUsize::new( 0 );
+static AGGREGATED : AtomicUsize = AtomicUsize::new( 0 );
+
+// how far the scan got, for the progress line
+static SCANNED  : AtomicU64 = AtomicU64::new( 0 );
+static FOUND    : AtomicU64 = AtomicU64::new( 0 );
+static SCANNING : Mutex<Option<PathBuf>> = Mutex::new( None );
+static STARTED  : Mutex<Option<Instant>> = Mutex::new( None );
+static PROGRESS : AtomicBool = AtomicBool::new( false );
+// SIGUSR1 asks for how the scan goes, like dd does
+static STATUS_ASKED : AtomicBool = AtomicBool::new( false );
+
+extern "C" fn on_status( _signal : libc::c_int ) {
+    STATUS_ASKED.store( true, Ordering::Relaxed );
+}
+// Ctrl-C stops the scan, and the report shows what it found so far
+static INTERRUPTED : AtomicBool = AtomicBool::new( false );
+
+extern "C" fn on_interrupt( _signal : libc::c_int ) {
+    INTERRUPTED.store( true, Ordering::Relaxed );
+    // a second one quits for good
+    unsafe { libc::signal( libc::SIGINT, libc::SIG_DFL ) };
+}
+
+// directories walked when following symlinks, which may loop, with the first path in order that walked each
+static VISITED : Mutex<Option<HashMap<(u64, u64), Order>>> = Mutex::new( None );
+// statx is missing before Linux 4.11, and some sandboxes forbid it
+#[cfg(target_os = "linux")]
+static NO_STATX : AtomicBool = AtomicBool::new( false );
+// io_uring rings not in use, scan threads are started for each directory so they pass them on
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+static RINGS    : Mutex<Vec<io_uring::IoUring>> = Mutex::new( Vec::new() );
+// the kernel doesn't give rings, or one failed
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+static NO_URING : AtomicBool = AtomicBool::new( false );
+// threads that can still be started to scan directories, besides the main one
+static WORKERS : AtomicUsize = AtomicUsize::new( 0 );
+// with --timings, where the time of the scan went for each top-level directory, <rest> first
+static TIMING  : AtomicBool = AtomicBool::new( false );
+static TIMINGS : Mutex<Vec<(String, Timings)>> = Mutex::new( Vec::new() );
+thread_local! {
+    static SLOT : Cell<usize> = const { Cell::new( 0 ) };
+    // the place among the paths given of the one being scanned
+    static ROOT : Cell<u32> = const { Cell::new( 0 ) };
+    // claims of the totals made below the entry being scanned, which takes them once it is done
+    static CLAIMS : RefCell<Vec<Claim>> = const { RefCell::new( Vec::new() ) };
+}
+
+#[derive(Clone, Copy, Default)]
+struct Timings {
+    readdir : Duration,
+    stat    : Duration,
+}
+
+// with --estimate, whether some directory was sampled, and the variance of the sampled sizes
+// (that of sampled directories inside sampled ones is left out, their sizes already vary with them)
+static SAMPLED  : AtomicBool = AtomicBool::new( false );
+static VARIANCE : Mutex<f64> = Mutex::new( 0.0 );
+// directories found by earlier scans, when the cache is on
+static CACHE : Mutex<Option<ScanCache>> = Mutex::new( None );
+
+// whether no path before rel in order walked the directory id, which is then walked from rel
+// a link to a directory being walked comes later than it, so loops stop
+fn first_walk( id : (u64, u64), rel : &Path ) -> bool {
+    let order = ( ROOT.with( Cell::get ), rel.to_path_buf() );
+    let mut visited = VISITED.lock().unwrap();
+    let visited = visited.get_or_insert_with( HashMap::new );
+    match visited.get( &id ) {
+        Some(first) if *first <= order => false,
+        _                              => {
+            visited.insert( id, order );
+            true
+        },
+    }
+}
+
+// a hard linked file, or a directory walked with -L, for the scan of the entry holding it to place
+fn claim( rel : &Path, id : (u64, u64), totals : Totals, kept : Totals ) {
+    let order = ( ROOT.with( Cell::get ), rel.to_path_buf() );
+    CLAIMS.with_borrow_mut( |claims| claims.push( Claim { order, id, totals, kept, at : UNPLACED, own : false } ) );
+}
 
 pub enum XResult<T,S> {
     XOk(T),
@@ -67,12 +194,326 @@ pub enum XResult<T,S> {
 }
 use XResult::{XOk, XExit, XErr};
 
+// an entry of a scan, plain data in the arena of its tree, so the tree is freed all at once
+#[derive(Clone, Copy)]
 struct Entry {
-    name    : String,
+    name    : u32,            // in the names of the tree
+    path    : u32,            // the path of a root as given, NO_NAME below it where paths follow the parents
+    parent  : u32,            // NO_PARENT for roots and collections
     bytes   : u64,
-    color   : Option<String>, // TODO reference
-    last    : bool,
-    entries : Option<Vec<Entry>>,
+    totals  : Totals,
+    dev     : u64,
+    ino     : u64,
+    mode    : u32,            // as lstat has it, 0 for what isn't on disk
+    uid     : u32,
+    gid     : u32,
+    target  : u32,            // where a link points, in the names of the tree, NO_NAME for the rest
+    color   : Option<Color>,
+    aggregated : bool,
+    entries : Option<( u32, u32 )>, // where the children start in the arena and how many, None if not listed
+}
+
+const NO_NAME   : u32 = u32::MAX;
+const NO_PARENT : u32 = u32::MAX;
+
+// where a path comes in the scan, by the path given it is below and its path from there
+type Order = ( u32, PathBuf );
+
+// a hard linked file, or a directory walked with -L, which other paths may reach as well
+// every path counts it while the threads scan, then only the first in order keeps it
+#[derive(Clone)]
+struct Claim {
+    order  : Order,
+    id     : ( u64, u64 ),
+    totals : Totals,          // what it added to the entries holding it
+    kept   : Totals,          // what it still adds when it isn't first, a directory counts itself
+    at     : u32,             // its entry, or the lowest one holding it, UNPLACED until that has a place
+    own    : bool,            // whether at is the entry of the claimed path
+}
+
+const UNPLACED : u32 = u32::MAX;
+const DROPPED  : u32 = u32::MAX - 1;
+
+// the entries of a scan, where the children of each directory are next to each other
+#[derive(Clone, Default)]
+struct Tree {
+    nodes  : Arena,
+    names  : Names,
+    top    : usize,           // the root, or the collection of the roots of multiple paths
+    claims : Vec<Claim>,      // until the scan is settled
+}
+
+// entries in chunks, each twice as large as the one before, so an entry never moves once in
+// and a growing tree isn't copied over and over the way a growing Vec is
+#[derive(Clone, Default)]
+struct Arena {
+    chunks : Vec<Vec<Entry>>,
+    len    : usize,
+}
+
+const FIRST_CHUNK : usize = 64;
+
+impl Arena {
+    fn len( &self ) -> usize {
+        self.len
+    }
+
+    fn push( &mut self, entry : Entry ) {
+        let ( chunk, _ ) = Arena::locate( self.len );
+        if chunk == self.chunks.len() {
+            self.chunks.push( Vec::with_capacity( FIRST_CHUNK << chunk ) );
+        }
+        self.chunks[chunk].push( entry );
+        self.len += 1;
+    }
+
+    // the chunk of an entry, and its place in there
+    fn locate( id : usize ) -> ( usize, usize ) {
+        let chunk = ( id / FIRST_CHUNK + 1 ).ilog2() as usize;
+        ( chunk, id - FIRST_CHUNK * ( ( 1 << chunk ) - 1 ) )
+    }
+
+    // len entries from first, which may span chunks
+    fn range( &self, first : u32, len : u32 ) -> impl Iterator<Item = &Entry> {
+        ( first as usize..( first + len ) as usize ).map( move |id| &self[id] )
+    }
+}
+
+impl std::ops::Index<usize> for Arena {
+    type Output = Entry;
+    fn index( &self, id : usize ) -> &Entry {
+        let ( chunk, at ) = Arena::locate( id );
+        &self.chunks[chunk][at]
+    }
+}
+
+impl std::ops::IndexMut<usize> for Arena {
+    fn index_mut( &mut self, id : usize ) -> &mut Entry {
+        let ( chunk, at ) = Arena::locate( id );
+        &mut self.chunks[chunk][at]
+    }
+}
+
+impl Extend<Entry> for Arena {
+    fn extend<I : IntoIterator<Item = Entry>>( &mut self, entries : I ) {
+        for entry in entries {
+            self.push( entry );
+        }
+    }
+}
+
+impl IntoIterator for Arena {
+    type Item = Entry;
+    type IntoIter = std::iter::Flatten<std::vec::IntoIter<Vec<Entry>>>;
+    fn into_iter( self ) -> Self::IntoIter {
+        self.chunks.into_iter().flatten()
+    }
+}
+
+// names of a tree, kept once however often they repeat, as the bytes the filesystem has
+#[derive(Clone, Default)]
+struct Names {
+    text  : Vec<u8>,
+    spans : Vec<( usize, usize )>,
+    ids   : HashMap<u64, u32>,  // by hash, a name whose hash is taken is just kept again
+}
+
+impl Names {
+    fn add( &mut self, name : &[u8] ) -> u32 {
+        let hash = fnv1a( name );
+        if let Some(&id) = self.ids.get( &hash ) {
+            if self.get( id ) == name { return id }
+        }
+        let id = self.spans.len() as u32;
+        self.spans.push( ( self.text.len(), name.len() ) );
+        self.text.extend_from_slice( name );
+        self.ids.entry( hash ).or_insert( id );
+        id
+    }
+
+    fn get( &self, id : u32 ) -> &[u8] {
+        let ( start, len ) = self.spans[id as usize];
+        &self.text[start..start + len]
+    }
+
+    // as the report shows it, which it can't for names that aren't UTF-8
+    fn show( &self, id : u32 ) -> &str {
+        std::str::from_utf8( self.get( id ) ).unwrap_or( "[invalid name]" )
+    }
+}
+
+// FNV-1a, a hash that doesn't change with the build
+fn fnv1a( bytes : &[u8] ) -> u64 {
+    bytes.iter().fold( 0xcbf29ce484222325u64, |hash, &b| ( hash ^ b as u64 ).wrapping_mul( 0x100000001b3 ) )
+}
+
+// an ANSI color code, by its place among the few that are used
+#[derive(Clone, Copy)]
+struct Color( u32 );
+
+// the codes are never freed, there are only as many as LS_COLORS has
+static PALETTE : Mutex<Vec<&'static str>> = Mutex::new( Vec::new() );
+
+impl Color {
+    fn new( code : &str ) -> Color {
+        let mut palette = PALETTE.lock().unwrap();
+        match palette.iter().position( |&c| c == code ) {
+            Some(i) => Color( i as u32 ),
+            None    => {
+                palette.push( Box::leak( code.to_string().into_boxed_str() ) );
+                Color( palette.len() as u32 - 1 )
+            },
+        }
+    }
+
+    fn code( self ) -> &'static str {
+        PALETTE.lock().unwrap()[self.0 as usize]
+    }
+}
+
+// sizes and counters added up over a tree
+#[derive(Clone, Copy, Default)]
+struct Totals {
+    size  : u64,              // apparent size
+    usage : u64,              // real disk usage
+    count : u64,              // files, not counting directories
+    dirs  : u64,
+    mtime : i64,              // newest modification time
+}
+
+impl Totals {
+    fn add( &mut self, other : &Totals ) {
+        self.size  += other.size;
+        self.usage += other.usage;
+        self.count += other.count;
+        self.dirs  += other.dirs;
+        self.mtime  = self.mtime.max( other.mtime );
+    }
+
+    // the newest modification time stays, what is left may well have it
+    fn sub( &mut self, other : &Totals ) {
+        self.size  = self.size.saturating_sub( other.size );
+        self.usage = self.usage.saturating_sub( other.usage );
+        self.count = self.count.saturating_sub( other.count );
+        self.dirs  = self.dirs.saturating_sub( other.dirs );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Column {
+    Size,
+    Percent,
+    Count,
+    Mtime,
+    Owner,
+    Perms,
+    Cumulative,
+    Alloc,      // apparent and allocated size
+}
+
+// how sizes are printed
+#[derive(Clone, Copy, PartialEq)]
+enum Units {
+    Binary,
+    Si,
+    Bytes,
+    Block( u64 ),
+}
+
+#[derive(Clone, Copy)]
+struct Sizes {
+    units : Units,
+    precision : usize,
+    group : Option<( char, char )>, // thousands separator and decimal point
+}
+
+// room for a column, in chars or as a percentage of what the fixed columns leave
+#[derive(Clone, Copy)]
+enum ColWidth {
+    Chars(usize),
+    Percent(usize),
+}
+
+// what ColWidth resolves to once the terminal is known
+#[derive(Clone, Copy)]
+struct Widths {
+    bar  : usize,
+    name : usize, // the tree goes in here too
+}
+
+// the order of the children of each directory
+#[derive(Clone, Copy, PartialEq)]
+enum SortKey {
+    Size,
+    Name,
+    Version,
+    Count,
+}
+
+// where the bar goes on each line
+#[derive(Clone, Copy, PartialEq)]
+enum Layout {
+    NameBar,
+    BarName,
+    BarLast,
+    BarBelow,
+}
+
+// glyphs the bars are drawn with
+#[derive(Clone, Copy, PartialEq)]
+enum BarStyle {
+    Blocks,
+    Braille,
+    Fine,
+    Ascii,
+}
+
+// glyphs the tree branches are drawn with
+#[derive(Clone, Copy, PartialEq)]
+enum TreeStyle {
+    Unicode,
+    Rounded,
+    Double,
+    Heavy,
+    Ascii,
+}
+
+// which hidden entries are left out
+#[derive(Clone, Copy, PartialEq)]
+enum Hidden {
+    Show,
+    Hide,
+    Dirs,
+    Files,
+}
+
+// how the cache of earlier scans is used
+#[derive(Clone, Copy, PartialEq)]
+enum CacheMode {
+    Use,
+    Refresh,    // written, but not read
+    Off,
+}
+
+enum Format {
+    Tree,
+    Csv( char ),
+    Dot,
+    Ncdu,
+    Du( char ),
+    Print0,
+    Json,
+    Template( String ),
+    List,
+    BiggestDirs( usize ),
+    ByType,
+    ByCategory,
+    AgeHistogram,
+    SizeHistogram,
+    Duplicates( bool ), // also compare contents
+    Stats,
+    Summarize,
+    ByMount,
 }
 
 pub struct Config {
@@ -80,27 +521,194 @@ pub struct Config {
     color_dict  : Dict<String>,
     depth       : u8,
     depth_flag  : bool,
-    bytes_flag  : bool,
+    min_depth   : u8,
+    fit_flag    : bool,
+    low_mem     : bool,
+    progress    : bool,
+    cache       : CacheMode,
+    cache_home  : Option<PathBuf>,
+    incremental : bool,
+    estimate    : bool,
+    timings     : bool,
+    path_depths : Vec<(PathBuf, u8)>,
+    sizes       : Sizes,
     usage_flag  : bool,
-    hiddn_flag  : bool,
-    ascii_flag  : bool,
+    inodes_flag : bool,
+    hidden      : Hidden,
+    count_hidden: bool,
+    ascii_flag  : bool,           // plain ASCII only, for -A and -o
+    color_flag  : bool,
+    links_host  : Option<String>, // hostname for hyperlinks, when they are on
+    bar_style   : BarStyle,
+    gradient    : Option<bool>, // whether the terminal takes 24-bit colors
+    no_bar_flag : bool,
+    layout      : Layout,
+    name_width  : Option<ColWidth>,
+    bar_width   : Option<ColWidth>,
+    pct_first   : bool,
+    pct_root    : bool,
+    tree_style  : TreeStyle,
+    indent      : usize,
     no_dir_flg  : bool,
+    footer_flag : bool,
+    total_flag  : bool,
+    gitignore_flag : bool,
+    caches_flag : bool,
+    icase_flag  : bool,
+    follow_flag : bool,
+    links_flag  : bool,
+    jobs        : usize,
+    empty_flag  : bool,
+    targets_flag : bool,
+    sparse_flag : bool,
+    full_flag   : bool,
+    abs_flag    : bool,
+    prune_flag  : bool,
+    collapse_flag : bool,
+    pseudo_fs   : Vec<(u64, u64)>,
+    uid         : Option<u32>,
+    gid         : Option<u32>,
+    types       : Vec<char>,
+    exts        : Vec<String>,
+    not_exts    : Vec<String>,
     aggr        : u64,
+    min_size    : u64,
+    max_size    : u64,
+    top         : usize,
+    max_lines   : Option<usize>,
+    sort        : SortKey,
+    dirs_first  : bool,
+    aggr_sorted : bool,
+    reverse     : bool,
     exclude     : Vec<String>,
+    include     : Vec<String>,
+    exclude_path: Vec<String>,
+    format      : Format,
+    columns     : Vec<Column>,
+    treemap     : Option<PathBuf>,
+    prometheus  : Option<PathBuf>,
+    output      : Option<PathBuf>,
+    save        : Option<PathBuf>,
+    load        : Option<PathBuf>,
+    import_du   : Option<PathBuf>,
+    #[cfg(feature = "parquet")]
+    parquet     : Option<PathBuf>,
+    #[cfg(feature = "sqlite")]
+    sqlite      : Option<PathBuf>,
 }
 
 fn init_opts() -> Options {
     let mut options = Options::new();
 
     options.optflagopt( "d", "depth"    , "show directories up to depth N (def 1)", "DEPTH" );
+    options.optopt(     "" , "paths-from", "read the paths to scan from FILE or -, one per line", "FILE" );
+    options.optmulti(   "" , "depth-for", "show PATH up to depth N, also given as PATH:N", "PATH=N" );
+    options.optopt(     "" , "min-depth", "start the report N levels below each path", "N" );
+    options.optflag(    "" , "fit"      , "show the most levels that fit in the terminal height" );
+    options.optflag(    "" , "no-progress", "don't show how the scan goes on stderr, when it is a terminal" );
+    options.optflag(    "" , "low-mem"  , "keep nothing below the depth shown (def 1), refusing what needs the whole tree" );
+    options.optflag(    "" , "cache"    , "reuse what earlier scans found in directories that haven't changed" );
+    options.optflag(    "" , "no-cache" , "leave the cache alone, also when --cache is given" );
+    options.optflag(    "" , "refresh"  , "scan everything again, rewriting the cache of earlier scans" );
+    options.optflag(    "" , "incremental", "like --cache, also for the levels shown" );
+    options.optflag(    "" , "estimate" , "size large directories below the depth shown from a sample of their entries" );
+    options.optflag(    "" , "timings"  , "tell on stderr where the time went, for each top-level directory" );
     options.optflagopt( "a", "aggr"     , "aggregate smaller than N B/KiB/MiB/GiB (def 1M)", "N[KMG]");
+    options.optopt(     "" , "min-size" , "hide entries smaller than N B/KiB/MiB/GiB", "N[KMG]" );
+    options.optopt(     "" , "max-size" , "hide entries larger than N, unless they hold smaller ones", "N[KMG]" );
+    options.optopt(     "" , "max-lines", "print at most N lines, keeping the largest entries", "N" );
+    options.optopt(     "" , "top"      , "show the N largest entries of each directory, aggregate the rest", "N" );
+    options.optopt(     "" , "sort"     , "order entries by size, name, version or count of files (def size)", "KEY" );
+    options.optflag(    "r", "reverse"  , "reverse the order, like smallest first"        );
+    options.optflag(    "" , "sort-aggregated", "sort <aggregated> among the entries instead of last" );
+    options.optflag(    "" , "dirs-first", "list directories before files"                );
     options.optflag(    "s", "summary"  , "equivalent to -da, or -d1 -a1M"                );
+    options.optflag(    "S", "summarize", "print one SIZE PATH line for each path, like du -s" );
     options.optflag(    "u", "usage"    , "report real disk usage instead of file size"   );
+    options.optflag(    "" , "apparent-size", "with --du, print file sizes instead of disk usage, like du" );
+    options.optflag(    "" , "inodes"   , "count inodes instead of bytes"                 );
     options.optflag(    "b", "bytes"    , "print sizes in bytes"                          );
+    options.optflag(    "" , "si"       , "print sizes in powers of 1000, like KB and MB" );
+    options.optopt(     "" , "precision", "decimals shown in sizes (def 2)", "N" );
+    options.optflag(    "" , "group-digits", "separate thousands in sizes, as the locale does" );
+    options.optopt(     "B", "block-size", "print sizes as a count of SIZE blocks, like du", "SIZE" );
     options.optflag(    "f", "files-only","skip directories for a fast local overview"    );
-    options.optmulti(   "x", "exclude"  , "exclude matching files or directories", "NAME" );
-    options.optflag(    "H", "no-hidden", "exclude hidden files"                          );
+    options.optmulti(   "x", "exclude"  , "exclude files or directories matching a glob", "GLOB" );
+    options.optflag(    "i", "ignore-case", "match exclude and include globs ignoring case" );
+    options.optmulti(   "" , "exclude-from", "read exclude globs from FILE, one per line", "FILE" );
+    options.optmulti(   "" , "exclude-path", "exclude one path, below the scanned directory or as given", "PATH" );
+    options.optflag(    "" , "exclude-caches", "skip directories tagged with a CACHEDIR.TAG file" );
+    options.optflag(    "" , "pseudo-fs", "also descend into /proc, /sys and other virtual filesystems" );
+    options.optopt(     "" , "user"     , "only count what USER owns, by name or uid", "USER" );
+    options.optopt(     "" , "group"    , "only count what belongs to GROUP, by name or gid", "GROUP" );
+    options.optopt(     "" , "type"     , "only count these types: f file, d dir, l link, b c block/char dev, s socket, p pipe", "f,d,l.." );
+    options.optopt(     "" , "ext"      , "only count files with these extensions", "mp4,mkv.." );
+    options.optopt(     "" , "not-ext"  , "don't count files with these extensions", "log,tmp.." );
+    options.optflag(    "" , "gitignore", "skip what .gitignore and .git/info/exclude ignore"  );
+    options.optmulti(   "" , "include"  , "only count files matching a glob, like 'src/**'", "GLOB" );
+    options.optflag(    "H", "no-hidden", "exclude hidden files, same as --hidden=hide"   );
+    options.optopt(     "" , "hidden"   , "show or hide hidden entries, or hide only dirs-only/files-only", "MODE" );
+    options.optflag(    "" , "count-hidden", "hide hidden entries but still count their size" );
+    options.optflag(    "L", "follow-symlinks", "descend into symlinked directories, each one once" );
+    options.optflag(    "l", "count-links", "count sizes many times if hard linked"       );
+    options.optopt(     "j", "jobs"     , "scan with N threads (def the number of CPUs)", "N" );
     options.optflag(    "A", "ascii"    , "ASCII characters only, no colors"              );
+    options.optopt(     "" , "color"    , "use colors always, never, or auto when printing to a terminal", "WHEN" );
+    options.optflag(    "" , "hyperlinks", "link names to their file:// URL, for terminals that open them" );
+    options.optopt(     "" , "theme"    , "color with a dark, light, solarized or mono theme instead of LS_COLORS", "THEME" );
+    options.optopt(     "" , "indent"   , "chars per tree level (def 3)", "N" );
+    options.optopt(     "" , "tree-style", "draw branches as unicode, rounded, double, heavy or ascii", "STYLE" );
+    options.optflag(    "" , "no-percent", "leave out the percentage column"              );
+    options.optflag(    "" , "percent-first", "print the percentage before the tree"        );
+    options.optopt(     "" , "percent-of", "take percentages of the parent or the root (def parent)", "BASE" );
+    options.optopt(     "" , "name-width", "width of the name column, in chars or N% (def 25%)", "WIDTH" );
+    options.optopt(     "" , "bar-width", "width of the bar column, in chars or N% (def 75%)", "WIDTH" );
+    options.optopt(     "" , "layout"   , "place the bar as name-bar, bar-name, bar-last or bar-below", "LAYOUT" );
+    options.optflag(    "" , "no-bar"   , "leave out the bar, giving its room to the names" );
+    options.optflag(    "" , "gradient" , "color bars from green to red by their percentage" );
+    options.optopt(     "" , "bar-style", "draw bars with blocks, braille, fine or ascii glyphs", "STYLE" );
+    options.optopt(     "" , "columns"  , "columns to show from size,percent,cumulative,count,mtime,owner,perms,alloc", "LIST" );
+    options.optflag(    "" , "mtime"    , "add a column with the newest modification time inside" );
+    options.optflag(    "" , "owner"    , "add a column with the owning user and group"   );
+    options.optflag(    "" , "perms"    , "add a column with the permission bits"         );
+    options.optflag(    "" , "sparse"   , "tag sparse files with what they allocate, and add an apparent/allocated column" );
+    options.optflag(    "" , "cumulative", "add a column with the running percentage of the parent" );
+    options.optflag(    "" , "csv"      , "print entries as comma separated values"       );
+    options.optflag(    "" , "tsv"      , "print entries as tab separated values"         );
+    options.optflag(    "" , "dot"      , "print the tree as a Graphviz digraph"          );
+    options.optflag(    "" , "ncdu"     , "print the scan in ncdu's JSON export format"   );
+    options.optflag(    "" , "json"     , "print the tree as JSON, compatible with tree -J"  );
+    options.optopt(     "" , "format"   , "print a line per entry, with {path} {name} {size} {bytes} {percent} {depth}", "TEMPLATE" );
+    options.optflag(    "" , "list"     , "print SIZE PATH lines, largest first, without the tree" );
+    options.optflag(    "" , "by-type"  , "print the size and count of files of each extension in the whole scan" );
+    options.optflag(    "" , "by-category", "print the size and count of video, images, audio, archives, code and documents" );
+    options.optflag(    "" , "age-histogram", "print the size and count of files by age, for each path" );
+    options.optflag(    "" , "size-histogram", "print the size and count of files by size class, for each path" );
+    options.optflag(    "" , "by-mount" , "print the size and count of files on each mounted filesystem" );
+    options.optflag(    "" , "stats"    , "print the average and median file size of each directory, up to the depth" );
+    options.optflag(    "" , "duplicates", "print sets of files of the same size, with the space they waste" );
+    options.optflag(    "" , "dup-hash" , "with --duplicates, also compare the contents by a hash" );
+    options.optopt(     "" , "biggest-dirs", "print the N largest directories at any depth below the paths, as SIZE PATH lines", "N" );
+    options.optflag(    "0", "print0"   , "print NUL separated path and size records"     );
+    options.optflagopt( "" , "du"       , "print du style lines, sized in b/k/h units (def k)", "UNIT" );
+    options.optflag(    "" , "absolute" , "name entries by their canonical absolute path"  );
+    options.optflag(    "" , "full-paths", "name entries by their path from the scanned root" );
+    options.optflag(    "" , "mark-empty", "tag directories without any file as (empty)"   );
+    options.optflag(    "" , "link-targets", "show symlinks as name -> target"                );
+    options.optflag(    "" , "prune-empty", "hide directories without any file"            );
+    options.optflag(    "" , "collapse" , "merge directories holding only one directory into a/b/c" );
+    options.optflag(    "" , "footer"   , "print totals, aggregated, excluded and errors after the tree" );
+    options.optflag(    "" , "total"    , "with multiple paths, name their sum total and show the share of each" );
+    options.optopt(     "o", "output"   , "write the report to FILE in ASCII, without colors", "FILE" );
+    options.optopt(     "" , "treemap"  , "also render a treemap of the tree to an SVG file", "FILE" );
+    options.optopt(     "" , "prometheus", "also write sizes as Prometheus metrics to FILE", "FILE" );
+    #[cfg(feature = "parquet")]
+    options.optopt(     "" , "parquet"  , "also write one row per entry to a Parquet FILE", "FILE" );
+    #[cfg(feature = "sqlite")]
+    options.optopt(     "" , "sqlite"   , "also add the scan to a SQLite database", "DB" );
+    options.optopt(     "" , "save"     , "also save the whole scan to FILE"     , "FILE" );
+    options.optopt(     "" , "load"     , "render a scan saved with --save"      , "FILE" );
+    options.optopt(     "" , "import-du", "render the output of 'du -ab' read from FILE or -", "FILE" );
     options.optflag(    "h", "help"     , "show help"                                     );
     options.optflag(    "v", "version"  , "print version number"                          );
     options
@@ -108,8 +716,10 @@ fn init_opts() -> Options {
 
 impl Config {
     pub fn new() -> XResult<Config, String> {
+        Config::from_args( env::args().collect() )
+    }
 
-        let args: Vec<String> = env::args().collect();
+    fn from_args( args : Vec<String> ) -> XResult<Config, String> {
         let program = args[0].clone();
 
         let options = init_opts();
@@ -127,17 +737,64 @@ impl Config {
             return XExit;
         };
 
-        let color_dict = create_color_dict();
+        let color_dict = match opt.opt_str("theme") {
+            Some(theme) => match theme_colors( &theme ) {
+                Some(colors) => create_color_dict( colors ),
+                None         => return XErr( format!( "invalid argument '{}'", theme ) ),
+            },
+            None => create_color_dict( &env::var("LS_COLORS").unwrap_or( "".to_string() ) ),
+        };
+
+        // --depth-for PATH=N, split at the last '=' so the path may have one too
+        let mut path_depths : Vec<(PathBuf, u8)> = Vec::new();
+        for arg in &opt.opt_strs("depth-for") {
+            let mut split = arg.rsplitn( 2, '=' );
+            match ( split.next().map( |n| n.parse() ), split.next() ) {
+                ( Some(Ok(n)), Some(path) ) => path_depths.push( ( PathBuf::from( path ), n ) ),
+                _                           => return XErr( format!( "invalid argument '{}'", arg ) ),
+            }
+        }
+
+        // '-' as a path reads the list from stdin
+        let mut args : Vec<String> = opt.free.iter().filter( |a| *a != "-" ).cloned().collect();
+        let paths_from = opt.opt_str("paths-from")
+                            .or( if opt.free.iter().any( |a| a == "-" ) { Some( "-".to_string() ) } else { None } );
+        if let Some(ref file) = paths_from {
+            if opt.opt_present("load") || opt.opt_present("import-du") {
+                return XErr( "--load and --import-du don't take paths".to_string() );
+            }
+            let list = if file == "-" {
+                let mut list = String::new();
+                io::stdin().read_to_string( &mut list ).map( |_| list )
+            } else {
+                fs::read_to_string( file )
+            };
+            match list {
+                Ok(list) => args.extend( list.lines().filter( |l| !l.is_empty() ).map( |l| l.to_string() ) ),
+                Err(err) => return XErr( format!( "couldn't read {} ({:?})", file, err.kind() ) ),
+            }
+            if args.is_empty() {
+                return XErr( "no paths to scan".to_string() );
+            }
+        }
 
         let mut paths : Vec<PathBuf> = Vec::new();
-        if opt.free.len() == 0 {
+        if args.is_empty() {
             let mut path = std::path::PathBuf::new();
-            path.push( ".".to_string() );
+            path.push( "." );
             paths.push( path );
         } else {
-            for opt in &opt.free {
+            for opt in &args {
                 let mut path = std::path::PathBuf::new();
-                path.push( &opt );
+                path.push( opt );
+                // a trailing :N sets the depth of that path, unless the name really ends like that
+                if !path.exists() {
+                    let mut split = opt.rsplitn( 2, ':' );
+                    if let ( Some(Ok(n)), Some(p) ) = ( split.next().map( |n| n.parse() ), split.next() ) {
+                        path = PathBuf::from( p );
+                        path_depths.push( ( path.clone(), n ) );
+                    }
+                }
                 paths.push( path );
             }
         }
@@ -148,42 +805,354 @@ impl Config {
             }
         }
 
+        let save = opt.opt_str("save").map( PathBuf::from );
+        if let Some(ref file) = save {
+            let missing = match file.extension().and_then( |e| e.to_str() ) {
+                Some("gz")  if !cfg!( feature = "gzip" ) => Some( "gzip" ),
+                Some("zst") if !cfg!( feature = "zstd" ) => Some( "zstd" ),
+                _                                        => None,
+            };
+            if let Some(feature) = missing {
+                return XErr( format!( "saving {} needs the '{}' feature", file.display(), feature ) );
+            }
+        }
+        let load = opt.opt_str("load").map( PathBuf::from );
+        let import_du = opt.opt_str("import-du").map( PathBuf::from );
+        if load.is_some() && import_du.is_some() {
+            return XErr( "--load and --import-du can't be combined".to_string() );
+        }
+        if let Some(file) = load.as_ref().or( import_du.as_ref() ) {
+            if !opt.free.is_empty() {
+                return XErr( "--load and --import-du don't take paths".to_string() );
+            }
+            if file.as_os_str() != "-" && !file.exists() {
+                return XErr( format!( "path {} doesn't exist", file.display() ) );
+            }
+        }
+
         let mut depth_flag = opt.opt_present("d");
         let depth_opt = opt.opt_str("d");
         let mut depth = depth_opt.unwrap_or("1".to_string()).parse().unwrap_or(1);
 
-        let bytes_flag = opt.opt_present("b");
-        let usage_flag = opt.opt_present("u");
-        let hiddn_flag = opt.opt_present("H");
-        let ascii_flag = opt.opt_present("A");
+        let min_depth = match opt.opt_str("min-depth").map( |n| n.parse::<u8>() ) {
+            Some(Ok(n)) => n,
+            Some(_)     => return XErr( format!( "invalid argument '{}'", opt.opt_str("min-depth").unwrap() ) ),
+            None        => 0,
+        };
+        let fit_flag = opt.opt_present("fit");
+        if fit_flag && depth_flag {
+            return XErr( "--fit and --depth can't be combined".to_string() );
+        }
+        let low_mem = opt.opt_present("low-mem");
+        // the whole tree is what --low-mem doesn't keep, so it shows one level unless told
+        if low_mem {
+            depth_flag = true;
+        }
+        let progress = !opt.opt_present("no-progress") && unsafe { libc::isatty( libc::STDERR_FILENO ) } == 1;
+        if opt.opt_present("no-cache") && opt.opt_present("refresh") {
+            return XErr( "--no-cache and --refresh can't be combined".to_string() );
+        }
+        let incremental = opt.opt_present("incremental");
+        // the cache has every file of the directories below the depth shown
+        if low_mem && ( opt.opt_present("cache") || opt.opt_present("refresh") || incremental ) {
+            return XErr( "--low-mem can't be used with --cache, --refresh or --incremental".to_string() );
+        }
+        let cache = if opt.opt_present("no-cache")                    { CacheMode::Off }
+                    else if opt.opt_present("refresh")                 { CacheMode::Refresh }
+                    else if opt.opt_present("cache") || incremental    { CacheMode::Use }
+                    else                                               { CacheMode::Off };
+        // $XDG_CACHE_HOME, or ~/.cache
+        let cache_home = env::var_os( "XDG_CACHE_HOME" ).map( PathBuf::from ).filter( |dir| dir.is_absolute() )
+                         .or_else( || env::var_os( "HOME" ).map( |home| Path::new( &home ).join( ".cache" ) ) );
+        let estimate = opt.opt_present("estimate");
+        let timings = opt.opt_present("timings");
+        if incremental && cache != CacheMode::Use {
+            return XErr( "--incremental can't be used with --no-cache or --refresh".to_string() );
+        }
+
+        let units = if opt.opt_present("inodes") { Units::Block( 1 ) }
+        else if let Some(size) = opt.opt_str("B") {
+            // a bare unit like -BK means one of it
+            let size = if size.chars().all( char::is_alphabetic ) { format!( "1{}", size ) } else { size };
+            match parse_size( &size ) {
+                Some(block) if block > 0 => Units::Block( block ),
+                _                        => return XErr( format!( "invalid block size '{}'", size ) ),
+            }
+        }
+        else if opt.opt_present("b")  { Units::Bytes  }
+        else if opt.opt_present("si") { Units::Si     }
+        else                          { Units::Binary };
+        let group = if opt.opt_present("group-digits") { Some( locale_separators() ) } else { None };
+        let precision = match opt.opt_str("precision").map( |n| n.parse::<usize>() ) {
+            Some(Ok(n)) if n <= 9 => n,
+            Some(_)               => return XErr( format!( "invalid argument '{}'", opt.opt_str("precision").unwrap() ) ),
+            None                  => 2,
+        };
+        let sizes = Sizes { units, precision, group };
+        if opt.opt_present("u") && opt.opt_present("apparent-size") {
+            return XErr( "--usage and --apparent-size can't be combined".to_string() );
+        }
+        // like du, --du reports disk usage, unless -b asks for the bytes of the files
+        let usage_flag = opt.opt_present("u") ||
+                         opt.opt_present("du") && !opt.opt_present("apparent-size") && !opt.opt_present("b");
+        let inodes_flag = opt.opt_present("inodes");
+        let count_hidden = opt.opt_present("count-hidden");
+        let mut hidden = match opt.opt_str("hidden").as_deref() {
+            None | Some("show") => Hidden::Show,
+            Some("hide")        => Hidden::Hide,
+            Some("dirs-only")   => Hidden::Dirs,
+            Some("files-only")  => Hidden::Files,
+            Some(mode)          => return XErr( format!( "invalid argument '{}'", mode ) ),
+        };
+        if hidden == Hidden::Show && ( opt.opt_present("H") || count_hidden ) {
+            hidden = Hidden::Hide;
+        }
+        let output     = opt.opt_str("o").map( PathBuf::from );
+        let ascii_flag = opt.opt_present("A") || output.is_some();
+        let color_flag = match opt.opt_str("color").as_deref() {
+            Some("always")        => true,
+            Some("never")         => false,
+            None | Some("auto")   => !ascii_flag && env::var_os("NO_COLOR").is_none_or( |v| v.is_empty() )
+                                     && unsafe { libc::isatty( libc::STDOUT_FILENO ) } == 1,
+            Some(when)            => return XErr( format!( "invalid argument '{}'", when ) ),
+        };
+        let bar_style  = match opt.opt_str("bar-style").as_deref() {
+            None if ascii_flag => BarStyle::Ascii,
+            None | Some("blocks") => BarStyle::Blocks,
+            Some("braille")    => BarStyle::Braille,
+            Some("fine")       => BarStyle::Fine,
+            Some("ascii")      => BarStyle::Ascii,
+            Some(style)        => return XErr( format!( "invalid argument '{}'", style ) ),
+        };
+        let no_bar_flag = opt.opt_present("no-bar");
+        let layout = match opt.opt_str("layout").as_deref() {
+            None | Some("name-bar") => Layout::NameBar,
+            Some("bar-name")        => Layout::BarName,
+            Some("bar-last")        => Layout::BarLast,
+            Some("bar-below")       => Layout::BarBelow,
+            Some(layout)            => return XErr( format!( "invalid argument '{}'", layout ) ),
+        };
+        let links_host = if opt.opt_present("hyperlinks") { Some( hostname() ) } else { None };
+        let gradient = if opt.opt_present("gradient") && color_flag {
+            let colorterm = env::var("COLORTERM").unwrap_or( "".to_string() );
+            Some( colorterm == "truecolor" || colorterm == "24bit" )
+        } else { None };
+        let mut widths = [ None, None ];
+        for ( i, flag ) in [ "name-width", "bar-width" ].iter().enumerate() {
+            if let Some(val) = opt.opt_str( flag ) {
+                match parse_width( &val ) {
+                    Some(width) => widths[i] = Some(width),
+                    None        => return XErr( format!( "invalid argument '{}'", val ) ),
+                }
+            }
+        }
+        let [ name_width, bar_width ] = widths;
+        let pct_first   = opt.opt_present("percent-first");
+        let pct_root    = match opt.opt_str("percent-of").as_deref() {
+            None | Some("parent") => false,
+            Some("root")          => true,
+            Some(base)            => return XErr( format!( "invalid argument '{}'", base ) ),
+        };
+        let indent = match opt.opt_str("indent").map( |n| n.parse::<usize>() ) {
+            Some(Ok(n)) if n > 0 => n,
+            Some(_)              => return XErr( format!( "invalid argument '{}'", opt.opt_str("indent").unwrap() ) ),
+            None                 => 3,
+        };
+        let tree_style = match opt.opt_str("tree-style").as_deref() {
+            None if ascii_flag => TreeStyle::Ascii,
+            None | Some("unicode") => TreeStyle::Unicode,
+            Some("rounded")    => TreeStyle::Rounded,
+            Some("double")     => TreeStyle::Double,
+            Some("heavy")      => TreeStyle::Heavy,
+            Some("ascii")      => TreeStyle::Ascii,
+            Some(style)        => return XErr( format!( "invalid argument '{}'", style ) ),
+        };
         let no_dir_flg = opt.opt_present("f");
+        let follow_flag = opt.opt_present("L");
+        let links_flag  = opt.opt_present("l");
+        let jobs = match opt.opt_str("jobs").map( |n| n.parse::<usize>() ) {
+            Some(Ok(n)) if n > 0 => n,
+            Some(_)              => return XErr( format!( "invalid argument '{}'", opt.opt_str("jobs").unwrap() ) ),
+            None                 => thread::available_parallelism().map( |n| n.get() ).unwrap_or( 1 ),
+        };
+        let empty_flag  = opt.opt_present("mark-empty");
+        let targets_flag = opt.opt_present("link-targets");
+        let sparse_flag = opt.opt_present("sparse");
+        let full_flag   = opt.opt_present("full-paths");
+        let abs_flag    = opt.opt_present("absolute");
+        let prune_flag  = opt.opt_present("prune-empty");
+        let collapse_flag = opt.opt_present("collapse");
 
         let mut aggr = if opt.opt_present("a") {
             let aggr_opt = opt.opt_str("a");
             let aggr_val = aggr_opt.unwrap_or("1M".to_string());
-
-            if !Regex::new(r"^\d+\D?$").unwrap().is_match( aggr_val.as_str() ){
-                return XErr( format!( "invalid argument '{}'", aggr_val ) );
+            match parse_size( &aggr_val ) {
+                Some(aggr) => aggr,
+                None       => return XErr( format!( "invalid argument '{}'", aggr_val ) ),
             }
+        } else {
+            0
+        };
 
-            let unit = aggr_val.matches(char::is_alphabetic).next().unwrap_or("B");
-            let num : Vec<&str> = aggr_val.matches(char::is_numeric).collect();
-            let num : u64       = num.concat().parse().unwrap();
+        let min_size = match opt.opt_str("min-size") {
+            Some(val) => match parse_size( &val ) {
+                Some(size) => size,
+                None       => return XErr( format!( "invalid argument '{}'", val ) ),
+            },
+            None      => 0,
+        };
+        let max_size = match opt.opt_str("max-size") {
+            Some(val) => match parse_size( &val ) {
+                Some(size) => size,
+                None       => return XErr( format!( "invalid argument '{}'", val ) ),
+            },
+            None      => u64::MAX,
+        };
+        let max_lines = match opt.opt_str("max-lines").map( |n| n.parse::<usize>() ) {
+            Some(Ok(n)) if n > 0 => Some( n ),
+            Some(_)              => return XErr( format!( "invalid argument '{}'", opt.opt_str("max-lines").unwrap() ) ),
+            None                 => None,
+        };
+        let sort = match opt.opt_str("sort").as_deref() {
+            None | Some("size") => SortKey::Size,
+            Some("name")        => SortKey::Name,
+            Some("version")     => SortKey::Version,
+            Some("count")       => SortKey::Count,
+            Some(key)           => return XErr( format!( "invalid argument '{}'", key ) ),
+        };
+        let dirs_first = opt.opt_present("dirs-first");
+        let aggr_sorted = opt.opt_present("sort-aggregated");
+        let reverse    = opt.opt_present("r");
+        let top = match opt.opt_str("top").map( |n| n.parse::<usize>() ) {
+            Some(Ok(n)) if n > 0 => n,
+            Some(_)              => return XErr( format!( "invalid argument '{}'", opt.opt_str("top").unwrap() ) ),
+            None                 => 0,
+        };
 
-            let factor = match unit {
-                "b" | "B" => 1024u64.pow(0),
-                "k" | "K" => 1024u64.pow(1),
-                "m" | "M" => 1024u64.pow(2),
-                "g" | "G" => 1024u64.pow(3),
-                "t" | "T" => 1024u64.pow(4),
-                _         => 1024u64.pow(0),
+        // globs with a slash match the path below the scanned directory, others the name
+        let mut exclude = opt.opt_strs("x");
+        for file in opt.opt_strs("exclude-from") {
+            let list = match fs::read_to_string( &file ) {
+                Ok(list) => list,
+                Err(err) => return XErr( format!( "couldn't read {} ({:?})", file, err.kind() ) ),
             };
-            num * factor
-        } else {
-            0
+            // blank lines and comments are ignored, like rsync does
+            exclude.extend( list.lines().map( |l| l.trim_end() )
+                                .filter( |l| !l.is_empty() && !l.starts_with( '#' ) )
+                                .map( |l| l.to_string() ) );
+        }
+        let icase_flag = opt.opt_present("i");
+        let fold = |p : &str| if icase_flag { p.to_lowercase() } else { p.to_string() };
+        let exclude = exclude.iter().map( |p| fold( p.trim_start_matches( "./" ) ) ).collect();
+        let include = opt.opt_strs("include").iter().map( |p| fold( p.trim_start_matches( "./" ) ) ).collect();
+        let exclude_path = opt.opt_strs("exclude-path").iter()
+                              .map( |p| fold( p.trim_start_matches( "./" ).trim_end_matches( '/' ) ) ).collect();
+
+        // only one report can be printed
+        let formats : Vec<&str> = FORMATS.iter().cloned().filter( |f| opt.opt_present( f ) ).collect();
+        if formats.len() > 1 {
+            return XErr( format!( "--{} and --{} can't be combined", formats[0], formats[1] ) );
+        }
+        let nformat = formats.len();
+        let footer_flag = opt.opt_present("footer");
+        let total_flag = opt.opt_present("total");
+        let gitignore_flag = opt.opt_present("gitignore");
+        let caches_flag = opt.opt_present("exclude-caches");
+        let uid = match opt.opt_str("user") {
+            Some(name) => match lookup_id( &name, false ) {
+                Some(uid) => Some(uid),
+                None      => return XErr( format!( "unknown user '{}'", name ) ),
+            },
+            None       => None,
+        };
+        let gid = match opt.opt_str("group") {
+            Some(name) => match lookup_id( &name, true ) {
+                Some(gid) => Some(gid),
+                None      => return XErr( format!( "unknown group '{}'", name ) ),
+            },
+            None       => None,
+        };
+        let types : Vec<char> = opt.opt_str("type").unwrap_or_default().chars().filter( |&c| c != ',' ).collect();
+        if let Some(c) = types.iter().find( |c| !"fdlbcsp".contains( **c ) ) {
+            return XErr( format!( "invalid type '{}'", c ) );
+        }
+        let ext_list = |name| opt.opt_str( name ).map_or( Vec::new(), |list : String| {
+            list.split( ',' ).map( |e| e.trim_start_matches( '.' ).to_lowercase() ).filter( |e| !e.is_empty() ).collect()
+        } );
+        let exts     = ext_list( "ext" );
+        let not_exts = ext_list( "not-ext" );
+        let pseudo_fs = if opt.opt_present("pseudo-fs") { Vec::new() } else { pseudo_mounts() };
+        if footer_flag && nformat > 0 {
+            return XErr( "--footer only applies to the tree".to_string() );
+        }
+        let format = match formats.first().cloned() {
+            None                   => Format::Tree,
+            Some("csv")            => Format::Csv( ',' ),
+            Some("tsv")            => Format::Csv( '\t' ),
+            Some("dot")            => Format::Dot,
+            Some("ncdu")           => Format::Ncdu,
+            Some("json")           => Format::Json,
+            Some("format")         => {
+                let template = opt.opt_str("format").unwrap_or_default();
+                Format::Template( template.replace( "\\t", "\t" ).replace( "\\n", "\n" ) )
+            },
+            Some("print0")         => Format::Print0,
+            Some("list")           => Format::List,
+            Some("summarize")      => Format::Summarize,
+            Some("by-type")        => Format::ByType,
+            Some("by-category")    => Format::ByCategory,
+            Some("age-histogram")  => Format::AgeHistogram,
+            Some("size-histogram") => Format::SizeHistogram,
+            Some("stats")          => Format::Stats,
+            Some("by-mount")       => Format::ByMount,
+            Some("duplicates")     => Format::Duplicates( opt.opt_present("dup-hash") ),
+            Some("biggest-dirs")   => {
+                let n = opt.opt_str("biggest-dirs").unwrap_or_default();
+                match n.parse::<usize>() {
+                    Ok(n)  => Format::BiggestDirs( n ),
+                    Err(_) => return XErr( format!( "invalid argument '{}'", n ) ),
+                }
+            },
+            Some(_)                => {
+                let unit = if opt.opt_present("b") { "b".to_string() }
+                           else { opt.opt_str("du").unwrap_or( "k".to_string() ) };
+                match unit.as_str() {
+                    "b" | "k" | "h" => Format::Du( unit.chars().next().unwrap() ),
+                    _               => return XErr( format!( "invalid unit '{}'", unit ) ),
+                }
+            },
         };
 
-        let exclude = opt.opt_strs("x");
+        let mut columns = Vec::new();
+        for col in opt.opt_str("columns").unwrap_or( "percent,size".to_string() ).split( ',' ) {
+            columns.push( match col {
+                "size"    => Column::Size,
+                "percent" => Column::Percent,
+                "count"   => Column::Count,
+                "mtime"   => Column::Mtime,
+                "owner"   => Column::Owner,
+                "perms"   => Column::Perms,
+                "cumulative" => Column::Cumulative,
+                "alloc"   => Column::Alloc,
+                _         => return XErr( format!( "invalid column '{}'", col ) ),
+            } );
+        }
+        for &( flag, col ) in &[ ( "mtime", Column::Mtime ), ( "owner", Column::Owner ), ( "perms", Column::Perms ),
+                                   ( "cumulative", Column::Cumulative ), ( "sparse", Column::Alloc ) ] {
+            if opt.opt_present( flag ) && !columns.contains( &col ) {
+                columns.push( col );
+            }
+        }
+        if opt.opt_present("no-percent") {
+            columns.retain( |col| *col != Column::Percent );
+        }
+
+        let treemap    = opt.opt_str("treemap").map( PathBuf::from );
+        let prometheus = opt.opt_str("prometheus").map( PathBuf::from );
+        #[cfg(feature = "parquet")]
+        let parquet    = opt.opt_str("parquet").map( PathBuf::from );
+        #[cfg(feature = "sqlite")]
+        let sqlite     = opt.opt_str("sqlite").map( PathBuf::from );
 
         if opt.opt_present("s") {
             depth_flag = true;
@@ -191,8 +1160,16 @@ impl Config {
             aggr       = 1024u64.pow(2);
         }
 
-        XOk( Config{ paths, color_dict, depth, depth_flag, bytes_flag, 
-            usage_flag, hiddn_flag, ascii_flag, no_dir_flg,  aggr, exclude } )
+        let cfg = Config{ paths, color_dict, depth, depth_flag, min_depth, fit_flag, low_mem, progress, cache, cache_home, incremental, estimate, timings, path_depths, sizes, 
+            usage_flag, inodes_flag, hidden, count_hidden, ascii_flag, color_flag, links_host, bar_style, gradient, no_bar_flag, layout, name_width, bar_width, pct_first, pct_root, tree_style, indent, no_dir_flg, footer_flag, total_flag, gitignore_flag, caches_flag, icase_flag, follow_flag, links_flag, jobs, empty_flag, targets_flag, sparse_flag, full_flag, abs_flag, prune_flag, collapse_flag, pseudo_fs, uid, gid, types, exts, not_exts, aggr, min_size, max_size, top, max_lines, sort, dirs_first, aggr_sorted, reverse, exclude, include, exclude_path, format, columns, treemap, prometheus, output, save, load, import_du,
+            #[cfg(feature = "parquet")] parquet,
+            #[cfg(feature = "sqlite")]  sqlite };
+
+        // below the depth shown, sizes are only added up while walking
+        if cfg.low_mem && ( cfg.save.is_some() || cfg.fit_flag || whole_scan( &cfg ) ) {
+            return XErr( "--low-mem can't be used with --save, --fit or reports over every file".to_string() );
+        }
+        XOk( cfg )
     }
 }
 
@@ -202,6 +1179,10 @@ fn try_is_symlink( path : &Path ) -> bool {
 }
 
 fn file_name_from_path( path : &Path ) -> String {
+    // only the likes of . and .. need to be resolved, what is found in directories has its name
+    if let Some(name) = path.file_name() {
+        return name.to_str().unwrap_or( "[invalid name]" ).to_string();
+    }
     let mut abspath = std::env::current_dir().unwrap();
     abspath.push( path );
 
@@ -214,374 +1195,4638 @@ fn file_name_from_path( path : &Path ) -> String {
            .to_str().unwrap_or( "[invalid name]" ).to_string()
 }
 
-fn try_read_dir( path : &Path ) -> Option<fs::ReadDir> {
-    if try_is_symlink( path ) { return None } // don't follow symlinks
-    match path.read_dir() {
-        Ok(dir_list) => Some(dir_list),
-        Err(err)     => { 
-            print_io_error( path, err );
-            None
-        },
-    }
+// symlinks are only stat'ed through when following them
+fn try_stat( path : &Path, follow : bool ) -> io::Result<fs::Metadata> {
+    timed( false, || if follow {
+        path.metadata().or_else( |_| path.symlink_metadata() )
+    } else {
+        path.symlink_metadata()
+    } )
 }
 
-fn try_bytes_from_path( path : &Path, usage_flag : bool ) -> u64 {
-
-    match path.symlink_metadata() {
-        #[cfg(target_os = "linux")]
-        Ok(metadata) => if usage_flag { metadata.st_blocks()*512 } else { metadata.st_size() },
-        #[cfg(target_os = "macos")]
-        Ok(metadata) => if usage_flag { metadata.blocks()*512 } else { metadata.size() },
-        Err(err)     => { 
-            print_io_error( path, err );
-            0
-        },
+// with --timings, what f takes adds to the readdir or stat time of the directory being scanned
+fn timed<T>( readdir : bool, f : impl FnOnce() -> T ) -> T {
+    if !TIMING.load( Ordering::Relaxed ) { return f() }
+    let start  = Instant::now();
+    let result = f();
+    let took   = start.elapsed();
+    let slot   = SLOT.with( Cell::get );
+    if let Some(( _, timings )) = TIMINGS.lock().unwrap().get_mut( slot ) {
+        if readdir { timings.readdir += took } else { timings.stat += took }
     }
+    result
 }
 
-fn path_from_dentry( entry : Result<fs::DirEntry, io::Error> ) -> Option<std::path::PathBuf> {
-    match entry {
-        Ok(entry) => {
-            Some( entry.path() )
-        },
-        Err(err)  => {
-            eprintln!( "Couldn't read entry ({:?})", err.kind() );
-            None
-        },
-    }
+// the slot a top-level directory times in, leaving the one it replaces
+fn enter_slot( path : &Path ) -> usize {
+    let mut timings = TIMINGS.lock().unwrap();
+    timings.push( ( path.to_string_lossy().into_owned(), Timings::default() ) );
+    SLOT.with( |slot| slot.replace( timings.len() - 1 ) )
 }
 
-fn print_io_error( path: &Path, err: io::Error ) {
-    eprintln!( "Couldn't read {} ({:?})", file_name_from_path( path ), err.kind() )
+// the fields of a stat that the scan uses
+#[derive(Clone, Copy)]
+struct Stat {
+    dev    : u64,
+    ino    : u64,
+    nlink  : u64,
+    is_dir : bool,
+    mode   : u32,     // type and permissions
+    uid    : u32,
+    gid    : u32,
+    size   : u64,
+    blocks : u64,
+    mtime  : i64,
+    link   : bool,    // the path itself is a link, also when followed
 }
 
-impl Entry {
-    fn new( path: &Path, cfg : &Config, depth : u8 ) -> Entry {
-        let name = file_name_from_path( path );
+impl Stat {
+    #[cfg(target_os = "linux")]
+    fn from_metadata( metadata : &fs::Metadata ) -> Stat {
+        Stat { dev : metadata.st_dev(), ino : metadata.st_ino(), nlink : metadata.st_nlink(), is_dir : metadata.is_dir(),
+               mode : metadata.st_mode(), uid : metadata.st_uid(), gid : metadata.st_gid(), size : metadata.st_size(),
+               blocks : metadata.st_blocks(), mtime : metadata.st_mtime(), link : metadata.file_type().is_symlink() }
+    }
 
-        // recursively create directory tree of entries up to depth
-        let depth = if cfg.depth_flag { depth - 1 } else { 1 };
+    #[cfg(target_os = "macos")]
+    fn from_metadata( metadata : &fs::Metadata ) -> Stat {
+        Stat { dev : metadata.dev() as u64, ino : metadata.ino(), nlink : metadata.nlink(), is_dir : metadata.is_dir(),
+               mode : metadata.mode(), uid : metadata.uid(), gid : metadata.gid(), size : metadata.size(),
+               blocks : metadata.blocks(), mtime : metadata.mtime(), link : metadata.file_type().is_symlink() }
+    }
 
-        let entries = if path.is_dir() && ( !cfg.depth_flag || depth > 0 ) {
-            let mut aggr_bytes = 0;
-            if let Some( dir_list ) = try_read_dir( path ) {
-                let mut vec : Vec<Entry> = Vec::new();
-                for entry in dir_list {
-                    if let Some( path ) = path_from_dentry( entry ) {
-                        let entry_name = &file_name_from_path(&path);
-
-                        // argument filters
-                        if cfg.exclude.iter().any( |p| entry_name == p ){ continue }
-                        if cfg.hiddn_flag && &entry_name[..1] == "."    { continue }
-                        if cfg.no_dir_flg && path.is_dir()              { continue }
-
-                        let entry = Entry::new( &path.as_path(), cfg, depth );
-                        if cfg.aggr > 0 && entry.bytes < cfg.aggr {
-                            aggr_bytes += entry.bytes;
-                        } else {
-                            vec.push( entry );
-                        }
-                    }
-                }
-                vec.sort_unstable_by( |a, b| b.bytes.cmp( &a.bytes ) );
-                if aggr_bytes > 0 {
-                    vec.push( Entry { 
-                        name: "<aggregated>".to_string(),
-                        bytes: aggr_bytes,
-                        color: None,
-                        last : true,
-                        entries: None,
-                    } );
-                }
+    #[cfg(target_os = "linux")]
+    fn from_statx( buf : &libc::statx ) -> Stat {
+        Stat {
+            dev    : libc::makedev( buf.stx_dev_major, buf.stx_dev_minor ),
+            ino    : buf.stx_ino,
+            nlink  : buf.stx_nlink as u64,
+            is_dir : buf.stx_mode as libc::mode_t & libc::S_IFMT == libc::S_IFDIR,
+            mode   : buf.stx_mode as u32,
+            uid    : buf.stx_uid,
+            gid    : buf.stx_gid,
+            size   : buf.stx_size,
+            blocks : buf.stx_blocks,
+            mtime  : buf.stx_mtime.tv_sec,
+            link   : buf.stx_mode as libc::mode_t & libc::S_IFMT == libc::S_IFLNK,
+        }
+    }
+}
 
-                let len = vec.len();
-                if len > 0 {
-                    vec[len-1].last = true;
-                }
+// what statx is asked for
+#[cfg(target_os = "linux")]
+const STATX_MASK : u32 = libc::STATX_TYPE | libc::STATX_MODE | libc::STATX_NLINK | libc::STATX_INO |
+                         libc::STATX_UID | libc::STATX_GID | libc::STATX_SIZE | libc::STATX_BLOCKS | libc::STATX_MTIME;
 
-                Some( vec )
-            } else { None }
-        } else { None };
+// only links are stat'ed again to follow them, so what they lead to is known to be behind a link
+fn try_stat_fields( path : &Path, follow : bool ) -> io::Result<Stat> {
+    let stat = stat_fields( path, false )?;
+    if !follow || !stat.link { return Ok( stat ) }
 
-        // calculate sizes
-        let bytes = if let Some(ref entries) = entries {
-            let mut total = try_bytes_from_path( path, cfg.usage_flag );
-            for entry in entries {
-                total += entry.bytes;
-            }
-            total
-        } else {
-            get_bytes( path, cfg.usage_flag )
-        };
+    // broken links are taken by themselves
+    Ok( stat_fields( path, true ).map( |target| Stat { link : true, ..target } ).unwrap_or( stat ) )
+}
 
-        // calculate color
-        let color = if !cfg.ascii_flag {
-            if let Some( col ) = color_from_path(path, &cfg.color_dict) {
-                Some( col.to_string() ) // TODO use references
-            } else { None }
-        } else { None };
+// like try_stat, asking the kernel only for what is used when it can
+fn stat_fields( path : &Path, follow : bool ) -> io::Result<Stat> {
+    #[cfg(target_os = "linux")]
+    {
+        if !NO_STATX.load( Ordering::Relaxed ) {
+            match timed( false, || try_statx( path, follow ) ) {
+                Err(ref err) if err.raw_os_error() == Some( libc::ENOSYS ) ||
+                                err.raw_os_error() == Some( libc::EPERM ) => NO_STATX.store( true, Ordering::Relaxed ),
+                result => return result,
+            }
+        }
+    }
+    try_stat( path, follow ).map( |metadata| Stat::from_metadata( &metadata ) )
+}
 
-        Entry { name, bytes, color, last: false, entries }
+#[cfg(target_os = "linux")]
+fn try_statx( path : &Path, follow : bool ) -> io::Result<Stat> {
+    let cpath = std::ffi::CString::new( path.as_os_str().as_bytes() )?;
+    let flags = libc::AT_STATX_SYNC_AS_STAT | if follow { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+    let mut buf : libc::statx = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statx( libc::AT_FDCWD, cpath.as_ptr(), flags, STATX_MASK, &mut buf ) } != 0 {
+        return Err( io::Error::last_os_error() );
     }
+    Ok( Stat::from_statx( &buf ) )
+}
 
-    fn print_entries( &self, open_parents : Vec<bool>, parent_vals : Vec<u64>, 
-                      bytes_flag : bool, ascii_flag : bool, bar_width : usize, tree_name_width : usize ) {
-        if let Some(ref entries) = self.entries {
-            for entry in entries {
-                let mut op    = open_parents.clone();
-                let mut bytes = parent_vals.clone();
-                bytes.push( entry.bytes );
+// stats of the files of a directory, queued together to io_uring when built with it
+// the listing tells these aren't links, so following them stats them only once
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+fn stat_all( paths : &[PathBuf], follow : bool ) -> Vec<io::Result<Stat>> {
+    paths.iter().map( |path| try_stat_fields( path, follow ) ).collect()
+}
 
-                // make sure the name column has the right length
-                let tree_width = (open_parents.len() + 1) * 3; // 3 chars per tree branch
-                if tree_name_width >= tree_width {
-                    let name_width  = tree_name_width - tree_width;
-                    let length = unicode_width::UnicodeWidthStr::width(entry.name.as_str());
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn stat_all( paths : &[PathBuf], follow : bool ) -> Vec<io::Result<Stat>> {
+    if !NO_URING.load( Ordering::Relaxed ) {
+        // a ring is only set up when all the others are taken, so there are at most as many as threads
+        let ring = RINGS.lock().unwrap().pop().or_else( || io_uring::IoUring::new( URING_SIZE as u32 ).ok() );
+        if let Some(mut ring) = ring {
+            if let Some(stats) = uring_stats( &mut ring, paths, follow ) {
+                RINGS.lock().unwrap().push( ring );
+                return stats;
+            }
+        }
+        NO_URING.store( true, Ordering::Relaxed );
+    }
+    paths.iter().map( |path| try_stat_fields( path, follow ) ).collect()
+}
 
-                    let mut name = entry.name.clone();
-                    name.truncate( name_width );
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+const URING_SIZE : usize = 256;
 
-                    // surround name by ANSII color escape sequences
-                    if let Some( ref col_str ) = entry.color {
-                        name.insert( 0, 'm' );
-                        name.insert( 0, 0o33 as char );
-                        name.insert( 1, '[' );
-                        name.insert_str( 2, col_str );
-                        name.push( 0o33 as char );
-                        name.push_str( "[0m" );
-                    }
+// None if the ring failed, which is then of no more use
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn uring_stats( ring : &mut io_uring::IoUring, paths : &[PathBuf], follow : bool ) -> Option<Vec<io::Result<Stat>>> {
+    use io_uring::{ opcode, types };
 
-                    if length < name_width {
-                        (length..name_width).for_each( |_| name.push( ' ' ) );
-                    }
+    let flags = libc::AT_STATX_SYNC_AS_STAT | if follow { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+    let mut stats = Vec::with_capacity( paths.len() );
+    for chunk in paths.chunks( URING_SIZE ) {
+        let cpaths : Vec<std::ffi::CString> = chunk.iter()
+            .map( |path| std::ffi::CString::new( path.as_os_str().as_bytes() ).unwrap_or_default() ).collect();
+        let mut bufs : Vec<libc::statx> = chunk.iter().map( |_| unsafe { std::mem::zeroed() } ).collect();
+        let mut results = vec![ 0; chunk.len() ];
 
-                    // draw the tree
-                    for open in &open_parents {
-                        if   *open { print!( "   " ); } 
-                        else       { print!( "│  " ); }
-                    }
-                    if   entry.last { print!( "└─ " ); op.push( true  ); }
-                    else            { print!( "├─ " ); op.push( false ); }
+        // the chunk fits the ring, and the buffers outlive the wait
+        for ( i, ( cpath, buf ) ) in cpaths.iter().zip( bufs.iter_mut() ).enumerate() {
+            let statx = opcode::Statx::new( types::Fd( libc::AT_FDCWD ), cpath.as_ptr(),
+                                            buf as *mut libc::statx as *mut types::statx )
+                            .flags( flags ).mask( STATX_MASK ).build().user_data( i as u64 );
+            unsafe { ring.submission().push( &statx ).ok()? };
+        }
+        // the kernel writes to the buffers until each statx completes, so they are only freed after that
+        let mut done   = 0;
+        let mut failed = false;
+        while done < chunk.len() {
+            match timed( false, || ring.submit_and_wait( if failed { 1 } else { chunk.len() - done } ) ) {
+                Err(ref err) if err.raw_os_error() == Some( libc::EINTR ) => continue,
+                Err(_) if failed => {
+                    // the ring can't be waited on, what is still queued may write any time
+                    std::mem::forget( bufs );
+                    std::mem::forget( cpaths );
+                    return None;
+                },
+                Err(_) => failed = true,
+                Ok(_)  => {},
+            }
+            for cqe in ring.completion() {
+                results[cqe.user_data() as usize] = cqe.result();
+                done += 1;
+            }
+        }
+        if failed {
+            return None;
+        }
 
-                    // print it
-                    println!( "{} {} {:>13}", 
-                              name,
-                              fmt_bar( &bytes, bar_width, ascii_flag ),
-                              fmt_size_str( entry.bytes, bytes_flag ) );
-                    if let Some(_) = entry.entries {
-                        entry.print_entries( op, bytes, bytes_flag, ascii_flag,
-                                             bar_width, tree_name_width );
-                    }
-                }
+        // failures, like broken links when following them, go the usual way
+        for ( ( path, buf ), result ) in chunk.iter().zip( &bufs ).zip( results ) {
+            stats.push( if result < 0 { try_stat_fields( path, follow ) } else { Ok( Stat::from_statx( buf ) ) } );
+        }
+    }
+    Some( stats )
+}
+
+// one of the threads left, if any
+fn take_worker() -> bool {
+    WORKERS.fetch_update( Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub( 1 ) ).is_ok()
+}
+
+// the paths in a directory, with whether each is a directory when its type already tells
+// stat is the directory's, which can't be a link unless links are followed
+fn try_read_dir( path : &Path, rel : &Path, stat : &Stat, follow : bool ) -> Option<Listing> {
+    if follow && !first_walk( ( stat.dev, stat.ino ), rel ) {
+        return None
+    }
+    *SCANNING.lock().unwrap() = Some( path.to_path_buf() );
+    match timed( true, || list_dir( path ) ) {
+        Ok(dir_list) => Some( dir_list.into_iter().map( |( path, dir )| ( path, dir, None ) ).collect() ),
+        Err(err)     => { 
+            print_io_error( path, err );
+            None
+        },
+    }
+}
+
+// what the report sizes entries by
+fn measure( totals : &Totals, cfg : &Config ) -> u64 {
+    if      cfg.inodes_flag { totals.count + totals.dirs }
+    else if cfg.usage_flag  { totals.usage }
+    else                    { totals.size  }
+}
+
+// totals of the path alone, without what a directory contains
+// hard linked files count in full, until the scan is settled and only their first path keeps them
+fn totals_from_stat( path : &Path, rel : &Path, stat : io::Result<Stat>, cfg : &Config ) -> Totals {
+    let totals = match stat {
+        Ok(stat) => {
+            let totals = Totals {
+                size  : stat.size,
+                usage : stat.blocks*512,
+                count : if stat.is_dir { 0 } else { 1 },
+                dirs  : if stat.is_dir { 1 } else { 0 },
+                mtime : stat.mtime,
+            };
+            if !cfg.links_flag && !stat.is_dir && stat.nlink > 1 {
+                claim( rel, ( stat.dev, stat.ino ), totals, Totals::default() );
             }
+            totals
+        },
+        Err(err)     => { 
+            print_io_error( path, err );
+            Totals::default()
+        },
+    };
+    SCANNED.fetch_add( 1, Ordering::Relaxed );
+    FOUND.fetch_add( measure( &totals, cfg ), Ordering::Relaxed );
+    if STATUS_ASKED.load( Ordering::Relaxed ) && STATUS_ASKED.swap( false, Ordering::Relaxed ) {
+        if PROGRESS.load( Ordering::Relaxed ) {
+            eprint!( "\r\x1b[K" );
         }
+        eprintln!( "{}", scan_status( cfg.sizes, "" ) );
     }
+    totals
+}
 
-    fn print( &self, bytes_flag : bool, ascii_flag : bool ) {
+fn try_dev_ino_from_path( path : &Path, follow : bool ) -> ( u64, u64 ) {
+    match try_stat_fields( path, follow ) {
+        Ok(stat) => ( stat.dev, stat.ino ),
+        Err(_)   => ( 0, 0 ),
+    }
+}
 
-        // calculate plot widths
-        let mut twidth = DEF_WIDTH; 
-        let size = terminal_size();
-        if let Some( ( Width(w), Height(_h) ) ) = size {
-            twidth = w;
-        } else {
-            eprintln!("Unable to get terminal size");
+// getdents64 returns the type of each entry with its name, sparing a stat for most of them
+#[cfg(target_os = "linux")]
+fn list_dir( path : &Path ) -> io::Result<Vec<(PathBuf, Option<bool>)>> {
+    let cpath = std::ffi::CString::new( path.as_os_str().as_bytes() )?;
+    let fd = unsafe { libc::open( cpath.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC ) };
+    if fd < 0 {
+        return Err( io::Error::last_os_error() );
+    }
+
+    let mut list = Vec::new();
+    let mut buf  = vec![ 0u8; 32 * 1024 ];
+    loop {
+        let read = unsafe { libc::syscall( libc::SYS_getdents64, fd, buf.as_mut_ptr(), buf.len() ) };
+        if read < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close( fd ) };
+            return Err( err );
         }
-        let size_width      = 15;
-        let var_width       = twidth - size_width;
-        let bar_width       = var_width as usize * 75 / 100;
-        let tree_name_width = var_width as usize * 25 / 100;
+        if read == 0 { break }
 
-        // initalize
-        let     open_parents : Vec<bool> = Vec::new();
-        let mut parent_vals  : Vec<u64>  = Vec::new();
-        parent_vals.push( self.bytes );
+        // records of d_ino: u64, d_off: i64, d_reclen: u16, d_type: u8, then the name and a NUL
+        let mut pos = 0;
+        while pos < read as usize {
+            let reclen = u16::from_ne_bytes( [ buf[pos + 16], buf[pos + 17] ] ) as usize;
+            let kind   = buf[pos + 18];
+            let name   = &buf[pos + 19 .. pos + reclen];
+            let name   = &name[.. name.iter().position( |&b| b == 0 ).unwrap_or( name.len() )];
+            pos += reclen;
+            if name == b"." || name == b".." { continue }
 
-        // print
-        println!( "[ {} {} ]", self.name, fmt_size_str( self.bytes, bytes_flag ) );
-        self.print_entries( open_parents, parent_vals, bytes_flag, ascii_flag,
-                            bar_width, tree_name_width );
+            // links may lead to directories, and some filesystems don't fill the type
+            let dir = match kind {
+                libc::DT_DIR                  => Some( true ),
+                libc::DT_LNK | libc::DT_UNKNOWN => None,
+                _                             => Some( false ),
+            };
+            list.push( ( path.join( OsStr::from_bytes( name ) ), dir ) );
+        }
     }
+    unsafe { libc::close( fd ) };
+    Ok( list )
 }
 
-fn fmt_bar( bytes : &Vec<u64>, width : usize, ascii_flag : bool ) -> String {
-    let width = width as u64 - 2 - 5; // not including bars and percentage
+#[cfg(not(target_os = "linux"))]
+fn list_dir( path : &Path ) -> io::Result<Vec<(PathBuf, Option<bool>)>> {
+    Ok( path.read_dir()?.filter_map( path_from_dentry ).map( |path| ( path, None ) ).collect() )
+}
 
-    let mut str = String::with_capacity( width as usize );
-    str.push( '│' );
+#[cfg(not(target_os = "linux"))]
+fn path_from_dentry( entry : Result<fs::DirEntry, io::Error> ) -> Option<std::path::PathBuf> {
+    match entry {
+        Ok(entry) => {
+            Some( entry.path() )
+        },
+        Err(err)  => {
+            eprintln!( "Couldn't read entry ({:?})", err.kind() );
+            ERRORS.fetch_add( 1, Ordering::Relaxed );
+            None
+        },
+    }
+}
 
-    let mut bytesi = bytes.iter();
-    let mut total  = bytesi.next().unwrap();
-    let mut part   = bytesi.next().unwrap();
-    let mut bars   = ( part * width ) / total;
-    let mut pos    = width - bars;
+fn print_io_error( path: &Path, err: io::Error ) {
+    if PROGRESS.load( Ordering::Relaxed ) {
+        eprint!( "\r\x1b[K" );
+    }
+    eprintln!( "Couldn't read {} ({:?})", file_name_from_path( path ), err.kind() );
+    ERRORS.fetch_add( 1, Ordering::Relaxed );
+}
 
-    let block_char = if ascii_flag { vec![ ' ', '#' ] } else { vec![ ' ', '░', '▒', '▓', '█' ] };
-    let mut chr    = 0;
-    let levels = bytes.len() - 1;
+impl Tree {
+    // a depth of None scans the whole tree, rel is the path below the scanned directory
+    // stat is the one of path, taken by the caller before filtering it
+    // the entry of path is returned for the caller to place among its siblings, what is below it is placed already
+    fn scan( &mut self, path: &Path, rel : &Path, ignores : &[Ignore], cfg : &Config, depth : Option<u8>, stat : io::Result<Stat> ) -> Entry {
+        let is_dir = stat.as_ref().is_ok_and( |stat| stat.is_dir );
+        let slot = if TIMING.load( Ordering::Relaxed ) && rel.components().count() == 1 && is_dir {
+            Some( enter_slot( path ) )
+        } else { None };
 
-    for x in 0..width {
-        if x > pos {
-            total = part;
-            part  = bytesi.next().unwrap_or(&0);
-            bars  = ( part * bars ) / total;
+        // recursively create directory tree of entries up to depth
+        let depth = depth.map( |d| d - 1 );
 
-            pos = width - bars;
-            chr += 1;
-            if chr == levels || chr >= block_char.len() {
-                chr = block_char.len() - 1;          // last level, solid '█'
+        // with --incremental, the levels shown are also taken from the cache when their directory is unchanged
+        let incremental = cfg.incremental && caching( cfg );
+        let mut fresh = true;
+        let listed = match stat {
+            Ok(ref stat) if stat.is_dir && depth.is_none_or( |d| d > 0 ) => {
+                let cached = if incremental { cached_dir( path, Some( stat ), cfg ) } else { None };
+                fresh = cached.is_none();
+                cached.map( |( _, list )| list ).or_else( || try_read_dir( path, rel, stat, cfg.follow_flag ) )
+            },
+            _ => None,
+        };
+        let entries = if let Some( dir_list ) = listed {
+            let ignores = read_ignores( path, rel, ignores, cfg );
+            let ignores = &ignores;
+            let mut record = if fresh && incremental { Some( dir_list.clone() ) } else { None };
+            let mut vec : Vec<Entry> = Vec::new();
+            // the claims of each entry in vec, next to each other in those of the tree
+            let mut held = Vec::new();
+            let mut dropped = false;
+            thread::scope( |scope| {
+                // scanned here, or still being scanned by another thread
+                let mut scans = Vec::new();
+                for ( slot, ( path, dir, cached ) ) in dir_list.into_iter().enumerate() {
+                    if INTERRUPTED.load( Ordering::Relaxed ) { break }
+                    let rel = rel.join( path.file_name().unwrap_or_default() );
+                    let ( is_dir, stat ) = match cached {
+                        Some(stat) => ( stat.is_dir, Some( Ok( stat ) ) ),
+                        None       => listed_type( &path, dir, cfg ),
+                    };
+                    if skipped( cfg, &path, &rel, ignores, is_dir ) { continue }
+                    let stat = stat.unwrap_or_else( || try_stat_fields( &path, cfg.follow_flag ) );
+                    // directories are stat'ed again next time, to tell whether they changed
+                    if let ( Some(record), &Ok(stat) ) = ( record.as_mut(), &stat ) {
+                        if !stat.is_dir { record[slot].2 = Some( stat ) }
+                    }
+                    if skipped_by_stat( cfg, &path, &rel, &stat ) { continue }
+
+                    if WORKERS.load( Ordering::Relaxed ) > 0 && is_dir && take_worker() {
+                        let slot = SLOT.with( Cell::get );
+                        let root = ROOT.with( Cell::get );
+                        scans.push( Err( scope.spawn( move || {
+                            SLOT.with( |s| s.set( slot ) );
+                            ROOT.with( |r| r.set( root ) );
+                            // a tree of its own, grafted on this one once the thread is done
+                            let mut tree = Tree::default();
+                            let entry = tree.scan( &path, &rel, ignores, cfg, depth, stat );
+                            WORKERS.fetch_add( 1, Ordering::SeqCst );
+                            ( tree, entry )
+                        } ) ) );
+                    } else {
+                        let mark  = self.claims.len();
+                        let entry = self.scan( &path, &rel, ignores, cfg, depth, stat );
+                        scans.push( Ok( ( entry, mark..self.claims.len() ) ) );
+                    }
+                }
+                for scan in scans {
+                    let ( entry, claims ) = match scan {
+                        Ok(scan)    => scan,
+                        Err(thread) => {
+                            let ( tree, entry ) = thread.join().unwrap();
+                            let mark  = self.claims.len();
+                            let entry = self.graft( tree, entry );
+                            ( entry, mark..self.claims.len() )
+                        },
+                    };
+                    if entry.totals.count + entry.totals.dirs > 0 || !file_filters( cfg ) {
+                        vec.push( entry );
+                        held.push( claims );
+                    } else {
+                        for claim in &mut self.claims[claims] { claim.at = DROPPED }
+                        dropped = true;
+                    }
+                }
+            } );
+            if let ( Some(record), Ok(stat) ) = ( record, &stat ) {
+                if !INTERRUPTED.load( Ordering::Relaxed ) {
+                    cache_dir( path, stat, record );
+                }
+            }
+            let ( first, len ) = self.place( vec );
+            for ( id, claims ) in ( first.. ).zip( held ) {
+                for claim in self.claims[claims].iter_mut().filter( |claim| claim.at == UNPLACED ) {
+                    claim.at = id;
+                }
+            }
+            if dropped {
+                self.claims.retain( |claim| claim.at != DROPPED );
+            }
+            Some( ( first, len ) )
+        } else { None };
+
+        // calculate color, links are colored as such even when followed
+        let color = if cfg.color_flag {
+            color_from_stat( path, stat.as_ref().ok(), &cfg.color_dict ).map( Color::new )
+        } else { None };
+        let ( dev, ino ) = stat.as_ref().map_or( ( 0, 0 ), |stat| ( stat.dev, stat.ino ) );
+        let ( uid, gid ) = stat.as_ref().map_or( ( 0, 0 ), |stat| ( stat.uid, stat.gid ) );
+        // a followed link shows as the link it is, lrwxrwxrwx
+        let mode = stat.as_ref().map_or( 0, |stat| if stat.link { 0o120777 } else { stat.mode } );
+        // read now, the link may be gone or point elsewhere once the tree is printed
+        let target = if file_kind( mode ) == 'l' {
+            path.read_link().map_or( NO_NAME, |target| self.names.add( target.as_os_str().as_bytes() ) )
+        } else { NO_NAME };
+
+        // calculate sizes
+        let totals = if let Some(( first, len )) = entries {
+            let mut totals = own_totals( path, rel, stat, cfg );
+            let own = totals;
+            for entry in self.nodes.range( first, len ) {
+                totals.add( &entry.totals );
+            }
+            // other links may lead here too
+            if cfg.follow_flag {
+                let order = ( ROOT.with( Cell::get ), rel.to_path_buf() );
+                self.claims.push( Claim { order, id : ( dev, ino ), totals, kept : own, at : UNPLACED, own : true } );
             }
+            totals
+        } else {
+            stat_totals( path, rel, ignores, cfg, stat )
+        };
+        let bytes = measure( &totals, cfg );
+        // what was claimed below, the caller places this entry
+        self.claims.append( &mut CLAIMS.take() );
+
+        if let Some(slot) = slot {
+            SLOT.with( |s| s.set( slot ) );
         }
-        str.push( block_char[chr] );
+
+        let ( name, path ) = self.add_names( path, rel.as_os_str().is_empty() );
+        Entry { name, path, parent : NO_PARENT, bytes, totals, dev, ino, mode, uid, gid, target, color, aggregated : false, entries }
     }
 
-    format!( "{}│ {:3}%", str, ( bytes[bytes.len()-1] * 100 ) / bytes[bytes.len()-2] )
-}
+    // roots are named as the report shows them and keep the path they were given, the rest have the names on disk
+    fn add_names( &mut self, path : &Path, root : bool ) -> ( u32, u32 ) {
+        let name = match path.file_name() {
+            Some(name) if !root => self.names.add( name.as_bytes() ),
+            _                   => self.names.add( file_name_from_path( path ).as_bytes() ),
+        };
+        ( name, if root { self.names.add( path.as_os_str().as_bytes() ) } else { NO_NAME } )
+    }
 
-fn fmt_size_str( bytes : u64, flag : bool ) -> String {
-    let b = bytes as f32;
-    if      bytes < 1024 || flag   { format!( "{:.2} B"  , bytes                    ) }
-    else if bytes < 1024u64.pow(2) { format!( "{:.2} KiB", b/1024.0                 ) }
-    else if bytes < 1024u64.pow(3) { format!( "{:.2} MiB", b/(1024u32.pow(2) as f32)) }
-    else if bytes < 1024u64.pow(4) { format!( "{:.2} GiB", b/(1024u32.pow(3) as f32)) }
-    else                           { format!( "{:.2} TiB", b/(1024u32.pow(4) as f32)) }
-}
+    // entries go next to each other at the end of the arena, their children learn where they are
+    fn place( &mut self, entries : Vec<Entry> ) -> ( u32, u32 ) {
+        let first = self.nodes.len();
+        self.nodes.extend( entries );
+        for id in first..self.nodes.len() {
+            self.adopt( id );
+        }
+        ( first as u32, ( self.nodes.len() - first ) as u32 )
+    }
 
-fn get_bytes( path: &Path, usage_flag : bool ) -> u64 {
-    if path.is_dir() {
-        let mut bytes : u64 = try_bytes_from_path( path, usage_flag );
-        if let Some(dir_list) = try_read_dir( path ) {
-            for entry in dir_list {
-                if let Some(path) = path_from_dentry( entry ) {
-                    bytes += get_bytes( &path, usage_flag );
-                }
+    fn adopt( &mut self, id : usize ) {
+        if let Some(( first, len )) = self.nodes[id].entries {
+            for child in first as usize..( first + len ) as usize {
+                self.nodes[child].parent = id as u32;
             }
         }
-        bytes
-    } else {
-        try_bytes_from_path( path, usage_flag )
     }
-}
 
-fn color_from_path<'a>( path : &Path, color_dict : &'a Dict<String> ) -> Option<&'a str> {
-    if try_is_symlink( path ) {
-        if path.read_link().unwrap().exists() {
-            if let Some( col ) = color_dict.get( "ln" ) {
-                return Some( &col );
+    // move what another thread scanned into this tree, its entry then points to what it has in here
+    fn graft( &mut self, tree : Tree, entry : Entry ) -> Entry {
+        let offset = self.nodes.len() as u32;
+        let names : Vec<u32> = ( 0..tree.names.spans.len() as u32 ).map( |id| self.names.add( tree.names.get( id ) ) ).collect();
+        let moved = |mut entry : Entry| {
+            entry.name = names[entry.name as usize];
+            if entry.path != NO_NAME { entry.path = names[entry.path as usize] }
+            if entry.target != NO_NAME { entry.target = names[entry.target as usize] }
+            if entry.parent != NO_PARENT { entry.parent += offset }
+            entry.entries = entry.entries.map( |( first, len )| ( first + offset, len ) );
+            entry
+        };
+        self.nodes.extend( tree.nodes.into_iter().map( moved ) );
+        self.claims.extend( tree.claims.into_iter().map( |mut claim| {
+            if claim.at != UNPLACED { claim.at += offset }
+            claim
+        } ) );
+        moved( entry )
+    }
+
+    // the roots of the scanned paths, under a collection when there are more than one
+    fn plant( &mut self, roots : Vec<Entry>, cfg : &Config ) {
+        let mut bytes  = 0;
+        let mut totals = Totals::default();
+        for root in &roots {
+            bytes += root.bytes;
+            totals.add( &root.totals );
+        }
+        let count = roots.len();
+        let entries = self.place( roots );
+        // what the roots claimed goes by the path each was given
+        for claim in self.claims.iter_mut().filter( |claim| claim.at == UNPLACED ) {
+            claim.at = entries.0 + claim.order.0;
+        }
+        if count == 1 {
+            self.top = entries.0 as usize;
+            return
+        }
+        let name = self.names.add( if cfg.total_flag { b"total" } else { b"<collection>" } );
+        self.nodes.push( Entry {
+            name,
+            path   : NO_NAME,
+            parent : NO_PARENT,
+            bytes,
+            totals,
+            dev    : 0,
+            ino    : 0,
+            mode   : 0,
+            uid    : 0,
+            gid    : 0,
+            target : NO_NAME,
+            color  : None,
+            aggregated : false,
+            entries : Some( entries ),
+        } );
+        self.top = self.nodes.len() - 1;
+        self.adopt( self.top );
+    }
+
+    // hard linked files, and directories reached again with -L, count at the first path in order that has them
+    // the threads counted them at every path, what the others added is taken off the entries holding them
+    fn settle( &mut self, cfg : &Config ) {
+        let mut claims = std::mem::take( &mut self.claims );
+        claims.sort_unstable_by( |a, b| a.order.cmp( &b.order ) );
+        let mut seen    = HashSet::new();
+        let mut pruned  : Option<&Order> = None;
+        let mut emptied = HashSet::new();
+        for claim in &claims {
+            // what is below a directory taken off went with it
+            if pruned.is_some_and( |( root, rel )| claim.order.0 == *root && claim.order.1.starts_with( rel ) ) { continue }
+            if seen.insert( claim.id ) { continue }
+
+            let mut lost = claim.totals;
+            lost.sub( &claim.kept );
+            let mut id = claim.at;
+            while id != NO_PARENT {
+                let entry = &mut self.nodes[id as usize];
+                entry.totals.sub( &lost );
+                entry.bytes = measure( &entry.totals, cfg );
+                if entry.totals.count + entry.totals.dirs == 0 && entry.parent != NO_PARENT {
+                    emptied.insert( entry.parent );
+                }
+                id = entry.parent;
             }
-        } else {
-            if let Some( col ) = color_dict.get( "or" )  {
-                return Some( &col );
+            // as if it hadn't been walked
+            if claim.own {
+                self.nodes[claim.at as usize].entries = None;
+            }
+            pruned = Some( &claim.order );
+        }
+
+        // the file filters leave out what has nothing left, as the scan does, deepest first
+        // so the children of the directories above are moved once they are
+        if !file_filters( cfg ) { return }
+        let depth = |tree : &Tree, mut id : u32| {
+            let mut depth = 0;
+            while id != NO_PARENT { id = tree.nodes[id as usize].parent; depth += 1 }
+            depth
+        };
+        let mut emptied : Vec<( usize, u32 )> = emptied.into_iter().map( |id| ( depth( self, id ), id ) ).collect();
+        emptied.sort_unstable_by( |a, b| b.cmp( a ) );
+        for ( _, id ) in emptied {
+            if let Some(( first, len )) = self.nodes[id as usize].entries {
+                let kept = self.nodes.range( first, len ).filter( |e| e.totals.count + e.totals.dirs > 0 ).copied().collect();
+                self.nodes[id as usize].entries = Some( self.place( kept ) );
             }
         }
     }
-    let metadata = path.symlink_metadata();
-    if metadata.is_ok() {
-        #[cfg(target_os = "linux")]
-        let mode = metadata.unwrap().st_mode();
-        #[cfg(target_os = "macos")]
-        let mode = metadata.unwrap().mode();
-        if path.is_dir() {
-            if mode & 0o002 != 0 {  // dir other writable
-                if let Some( col ) = color_dict.get( "ow" ) {
-                    return Some( &col );
-                }
+
+    fn node( &self, id : usize ) -> Node<'_> {
+        Node { tree : self, id }
+    }
+
+    fn top( &self ) -> Node<'_> {
+        self.node( self.top )
+    }
+
+    // the entries min_depth levels below replace the children, named by their path from here
+    fn lift( &mut self, id : usize, min_depth : u8, cfg : &Config ) {
+        let mut list = Vec::new();
+        if let Some(( first, len )) = self.nodes[id].entries {
+            for child in first..first + len {
+                self.descend( child as usize, min_depth - 1, Path::new( "" ), &mut list );
             }
-            if let Some( col ) = color_dict.get( "di" ) {
-                return Some( &col );
+        }
+        sort_entries( &mut list, &self.names, cfg );
+        self.nodes[id].entries = Some( self.place( list ) );
+        self.adopt( id );
+    }
+
+    fn descend( &mut self, id : usize, levels : u8, prefix : &Path, list : &mut Vec<Entry> ) {
+        let name = prefix.join( OsStr::from_bytes( self.names.get( self.nodes[id].name ) ) );
+        if levels == 0 {
+            let mut entry = self.nodes[id];
+            entry.name = self.names.add( name.as_os_str().as_bytes() );
+            list.push( entry );
+        } else if let Some(( first, len )) = self.nodes[id].entries {
+            for child in first..first + len {
+                self.descend( child as usize, levels - 1, &name, list );
             }
         }
-        if mode & 0o111 != 0 {  // executable
-            if let Some( col ) = color_dict.get( "ex" ) {
-                return Some( &col );
+    }
+
+    // hidden, but still counted in the size of its directory
+    fn listed( &self, entry : &Entry, cfg : &Config ) -> bool {
+        entry.bytes >= cfg.min_size && !( cfg.prune_flag && entry.is_empty_dir() ) &&
+        !( cfg.count_hidden && hides( cfg, self.names.get( entry.name ), entry.is_dir() ) )
+    }
+
+    // the lines arrange leaves for the children of each level, without arranging anything
+    // collapsed directories and big ones that turn out to lead nowhere are counted, they only take lines away
+    fn count_lines( &self, id : usize, cfg : &Config, level : usize, lines : &mut Vec<usize> ) {
+        let ( first, len ) = match self.nodes[id].entries {
+            Some(( first, len )) => ( first as usize, len as usize ),
+            None                 => return,
+        };
+        let mut kept = Vec::new();
+        let mut aggr_bytes = 0;
+        for child in first..first + len {
+            let entry = &self.nodes[child];
+            if !self.listed( entry, cfg ) { continue }
+            if cfg.aggr > 0 && entry.bytes < cfg.aggr {
+                aggr_bytes += entry.bytes;
+            } else if entry.bytes <= cfg.max_size || entry.entries.is_some() {
+                kept.push( child );
             }
         }
+        if cfg.top > 0 && kept.len() > cfg.top {
+            kept.sort_unstable_by_key( |&child| std::cmp::Reverse( self.nodes[child].bytes ) );
+            aggr_bytes += kept.split_off( cfg.top ).iter().map( |&child| self.nodes[child].bytes ).sum::<u64>();
+        }
+        if lines.len() <= level {
+            lines.resize( level + 1, 0 );
+        }
+        lines[level] += kept.len() + ( aggr_bytes > 0 && aggr_bytes <= cfg.max_size ) as usize;
+        for child in kept {
+            self.count_lines( child, cfg, level + 1, lines );
+        }
     }
-    if let Some( ext_str ) = path.extension() {
-        for col in color_dict {
-            if &col.key[..2] != "*." { continue }
-            let key = col.key.trim_left_matches( "*." );
-            if ext_str == key {
-                return Some( &color_dict.get( col.key.as_str() ).unwrap() );
+
+    // merge directories holding a single directory into one a/b/c entry
+    fn collapse( &mut self, id : usize ) {
+        if let Some(( first, len )) = self.nodes[id].entries {
+            for child in first as usize..( first + len ) as usize {
+                while let Some(( only, 1 )) = self.nodes[child].entries {
+                    let below = self.nodes[only as usize];
+                    if !below.is_dir() || below.aggregated { break }
+                    let mut name = self.names.get( self.nodes[child].name ).to_vec();
+                    name.push( b'/' );
+                    name.extend_from_slice( self.names.get( below.name ) );
+                    self.nodes[child].name    = self.names.add( &name );
+                    self.nodes[child].entries = below.entries;
+                    self.adopt( child );
+                }
+                self.collapse( child );
             }
         }
     }
-    if path.is_file() {
-        if let Some( col ) = color_dict.get( "fi" ) {
-            return Some( &col );
+
+    // drop what is smaller than min, and what is as small once ties run out
+    fn keep_largest( &mut self, id : usize, min : u64, ties : &mut usize ) {
+        if let Some(( first, len )) = self.nodes[id].entries {
+            let ( first, end ) = ( first as usize, ( first + len ) as usize );
+            let mut kept = first;
+            for child in first..end {
+                let bytes = self.nodes[child].bytes;
+                if bytes > min || bytes == min && *ties > 0 && { *ties -= 1; true } {
+                    self.nodes[kept] = self.nodes[child];
+                    kept += 1;
+                }
+            }
+            self.nodes[id].entries = Some(( first as u32, ( kept - first ) as u32 ));
+            for child in first..kept {
+                self.adopt( child );
+                self.keep_largest( child, min, ties );
+            }
         }
-        else { return None }
     }
-    // we are assuming it can only be a 'bd','cd'. can also be 'pi','so' or 'no'
-    if let Some( col ) = color_dict.get( "bd" ) {
-        return Some( &col );
+
+    // cut the tree at depth, aggregate small entries and sort what is left
+    fn arrange( &mut self, id : usize, cfg : &Config, depth : Option<u8> ) {
+        let depth = depth.map( |d| d - 1 );
+        if depth == Some( 0 ) {
+            self.nodes[id].entries = None;
+        }
+        if !cfg.color_flag {
+            self.nodes[id].color = None;
+        }
+
+        if let Some(( first, len )) = self.nodes[id].entries {
+            let mut aggr_bytes = 0;
+            let mut aggr_items = 0;
+            let mut aggr = Totals::default();
+            let mut vec : Vec<Entry> = Vec::new();
+            for child in first as usize..( first + len ) as usize {
+                let entry = self.nodes[child];
+                if !self.listed( &entry, cfg ) { continue }
+
+                if cfg.aggr > 0 && entry.bytes < cfg.aggr {
+                    aggr_bytes += entry.bytes;
+                    aggr_items += 1;
+                    aggr.add( &entry.totals );
+                    AGGREGATED.fetch_add( 1, Ordering::Relaxed );
+                } else {
+                    self.arrange( child, cfg, depth );
+                    let entry = self.nodes[child];
+                    // big entries stay only to lead to the small ones below them
+                    if entry.bytes <= cfg.max_size || entry.entries.is_some_and( |( _, len )| len > 0 ) {
+                        vec.push( entry );
+                    }
+                }
+            }
+            vec.sort_unstable_by_key( |e| std::cmp::Reverse( e.bytes ) );
+            if cfg.top > 0 && vec.len() > cfg.top {
+                for entry in vec.split_off( cfg.top ) {
+                    aggr_bytes += entry.bytes;
+                    aggr_items += 1;
+                    aggr.add( &entry.totals );
+                    AGGREGATED.fetch_add( 1, Ordering::Relaxed );
+                }
+            }
+            if cfg.sort != SortKey::Size || cfg.dirs_first || cfg.reverse {
+                sort_entries( &mut vec, &self.names, cfg );
+            }
+            if aggr_bytes > 0 && aggr_bytes <= cfg.max_size {
+                let name = format!( "<aggregated {} item{}>", aggr_items, if aggr_items == 1 { "" } else { "s" } );
+                vec.push( Entry { 
+                    name: self.names.add( name.as_bytes() ),
+                    path : NO_NAME,
+                    parent : id as u32,
+                    bytes: aggr_bytes,
+                    totals: aggr,
+                    dev  : 0,
+                    ino  : 0,
+                    mode : 0,
+                    uid  : 0,
+                    gid  : 0,
+                    target : NO_NAME,
+                    color: None,
+                    aggregated : true,
+                    entries: None,
+                } );
+                if cfg.aggr_sorted {
+                    sort_entries( &mut vec, &self.names, cfg );
+                }
+            }
+
+            // what is left takes the first places of the children, it is never more than they were
+            let len = vec.len();
+            for ( i, entry ) in vec.into_iter().enumerate() {
+                self.nodes[first as usize + i] = entry;
+            }
+            self.nodes[id].entries = Some(( first, len as u32 ));
+            for child in first as usize..first as usize + len {
+                self.adopt( child );
+            }
+        }
     }
-    None
 }
 
-fn print_usage( program: &str, opts: &Options ) {
-    let brief = format!( "Usage: {} [options] <path> [<path>..]", program );
-    print!( "{}", opts.usage( &brief ) );
-}
+impl Entry {
+    // also for directories cut at the depth, or read from a listing
+    fn is_dir( &self ) -> bool {
+        self.entries.is_some() || self.totals.dirs > 0
+    }
 
-fn create_color_dict() -> Dict<String> {
-    let mut color_dict = Dict::<String>::new();
-    let env_str = env::var("LS_COLORS").unwrap_or( "".to_string() );
-    for entry in env_str.split(':') {
-        if entry.len() == 0 { break; }
+    fn is_link( &self ) -> bool {
+        file_kind( self.mode ) == 'l'
+    }
 
-        let     line = entry.replace("\"","");
-        let mut line = line.split('=');
-        let key      = line.next().unwrap();
-        let val      = line.next().unwrap();
+    fn is_aggregated( &self ) -> bool {
+        self.aggregated
+    }
 
-        color_dict.add( key.to_string(), val.to_string() );
+    // files with holes, that allocate less than half of their size, links allocate nothing of theirs
+    // small files may be kept inline with no blocks of their own, so the holes have to add up to SPARSE_HOLES
+    fn is_sparse( &self ) -> bool {
+        !self.is_dir() && !self.is_link() && !self.is_aggregated() && self.totals.usage < self.totals.size / 2
+            && self.totals.size - self.totals.usage >= SPARSE_HOLES
+    }
+
+    // skeleton trees of directories count as empty too
+    fn is_empty_dir( &self ) -> bool {
+        self.totals.dirs > 0 && self.totals.count == 0
+    }
+
+    // the synthetic root of multiple paths is the only entry with neither a path nor a parent
+    fn is_collection( &self ) -> bool {
+        self.parent == NO_PARENT && self.path == NO_NAME
     }
-    color_dict
 }
 
-pub fn run( cfg: &Config ) {
-    let entry = if cfg.paths.len() == 1 {
-        Entry::new( cfg.paths[0].as_path(), &cfg, cfg.depth + 1 )
-    } else {
-        let mut bytes = 0;
-        let mut entries : Vec<Entry> = Vec::new();
+// an entry seen from its tree, which has its children, name and path
+#[derive(Clone, Copy)]
+struct Node<'a> {
+    tree : &'a Tree,
+    id   : usize,
+}
 
-        for path in &cfg.paths {
-            let e = Entry::new( path.as_path(), &cfg, cfg.depth + 1 );
-            bytes += e.bytes;
-            entries.push( e );
+impl<'a> std::ops::Deref for Node<'a> {
+    type Target = Entry;
+    fn deref( &self ) -> &Entry {
+        &self.tree.nodes[self.id]
+    }
+}
+
+impl<'a> Node<'a> {
+    fn children( self ) -> Option<impl ExactSizeIterator<Item = Node<'a>> + Clone> {
+        let tree = self.tree;
+        self.entries.map( move |( first, len )| ( first as usize..( first + len ) as usize ).map( move |id| Node { tree, id } ) )
+    }
+
+    fn name( self ) -> &'a str {
+        self.tree.names.show( self.tree.nodes[self.id].name )
+    }
+
+    // as read when the link was scanned
+    fn target( self ) -> Option<&'a Path> {
+        let target = self.tree.nodes[self.id].target;
+        if target == NO_NAME { None } else { Some( Path::new( OsStr::from_bytes( self.tree.names.get( target ) ) ) ) }
+    }
+
+    // rebuilt from the names up to the root, the collection of multiple paths has none
+    fn path( self ) -> PathBuf {
+        let ( nodes, names ) = ( &self.tree.nodes, &self.tree.names );
+        let mut below : Vec<&[u8]> = Vec::new();
+        let mut entry = &nodes[self.id];
+        while entry.path == NO_NAME {
+            if entry.parent == NO_PARENT { return PathBuf::new() }
+            if entry.aggregated {
+                // named <aggregated> within what it aggregates, lifting may have put that in its name
+                below.push( b"<aggregated>" );
+                if let Some(dir) = Path::new( OsStr::from_bytes( names.get( entry.name ) ) ).parent() {
+                    if !dir.as_os_str().is_empty() { below.push( dir.as_os_str().as_bytes() ) }
+                }
+            } else {
+                below.push( names.get( entry.name ) );
+            }
+            entry = &nodes[entry.parent as usize];
         }
-        let len = entries.len();
-        if len > 0 {
-            entries[len-1].last = true;
+        let mut path = PathBuf::from( OsStr::from_bytes( names.get( entry.path ) ) );
+        for name in below.iter().rev() {
+            path.push( OsStr::from_bytes( name ) );
         }
-        Entry { 
-            name    : "<collection>".to_string(),
-            bytes,
-            color   : None,
-            last    : false,
-            entries : Some(entries)
+        path
+    }
+
+    // this entry and everything below it
+    fn flatten( self, list : &mut Vec<Node<'a>> ) {
+        list.push( self );
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                entry.flatten( list );
+            }
         }
-    };
+    }
 
-    entry.print( cfg.bytes_flag, cfg.ascii_flag );
-}
+    fn print_entries( self, out : &mut dyn Write, open_parents : Vec<bool>, parent_vals : Vec<u64>, 
+                      prefix : &str, cfg : &Config, widths : Widths ) -> io::Result<()> {
+        if let Some(entries) = self.children() {
+            let ( line, tee, corner, dash ) = tree_glyphs( cfg.tree_style );
+            let blank  = branch( ' ',    ' ',  cfg.indent );
+            let pipe   = branch( line,   ' ',  cfg.indent );
+            let tee    = branch( tee,    dash, cfg.indent );
+            let corner = branch( corner, dash, cfg.indent );
+            let pct_base = if cfg.pct_root { parent_vals[0] } else { self.bytes };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+            let mut running = 0;
+            let count = entries.len();
+            for ( i, entry ) in entries.enumerate() {
+                let last = i + 1 == count;
+                running += entry.bytes;
+                let mut op    = open_parents.clone();
+                let mut bytes = parent_vals.clone();
+                bytes.push( entry.bytes );
 
-    #[test]
-    fn parse_ls_colors() {
-        let mut dict = Dict::<String>::new();
-        dict.add( "di".to_string(), "dircode".to_string() );
-        dict.add( "li".to_string(), "linkcod".to_string() );
-        dict.add( "*.mp3".to_string(), "mp3code".to_string() );
-        dict.add( "*.tar".to_string(), "tarcode".to_string() );
-        assert_eq!( "dircode", color_from_path( Path::new(".")       , &dict ).unwrap() );
-        assert_eq!( "mp3code", color_from_path( Path::new("test.mp3"), &dict ).unwrap() );
-        assert_eq!( "tarcode", color_from_path( Path::new("test.tar"), &dict ).unwrap() );
+                // roots of a collection already show their path, their children start over
+                let path = if cfg.abs_flag { entry.path().to_string_lossy().into_owned() }
+                           else if !cfg.full_flag || self.is_collection() { entry.name().to_string() }
+                           else { format!( "{}{}", prefix, entry.name() ) };
+
+                // make sure the name column has the right length
+                let tree_width = (open_parents.len() + 1) * cfg.indent;
+                if widths.name >= tree_width {
+                    let name_width  = widths.name - tree_width;
+                    let mut name = if cfg.empty_flag && entry.is_empty_dir() {
+                        format!( "{} (empty)", path )
+                    } else if cfg.sparse_flag && entry.is_sparse() {
+                        format!( "{} (sparse, {} allocated)", path, fmt_size_str( entry.totals.usage, cfg.sizes ) )
+                    } else if let Some(target) = entry.target().filter( |_| cfg.targets_flag ) {
+                        format!( "{} -> {}", path, target.display() )
+                    } else {
+                        path.clone()
+                    };
+                    name = truncate_name( &name, name_width, if cfg.ascii_flag { '~' } else { '…' } );
+                    let length = UnicodeWidthStr::width( name.as_str() );
+
+                    // surround name by ANSII color escape sequences
+                    if let Some( color ) = entry.color {
+                        name.insert( 0, 'm' );
+                        name.insert( 0, 0o33 as char );
+                        name.insert( 1, '[' );
+                        name.insert_str( 2, color.code() );
+                        name.push( 0o33 as char );
+                        name.push_str( "[0m" );
+                    }
+
+                    // OSC 8, for terminals that make links of it, on what was on disk when scanned
+                    if let Some( ref host ) = cfg.links_host {
+                        if entry.mode != 0 {
+                            name = format!( "\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", file_url( host, &entry.path() ), name );
+                        }
+                    }
+
+                    if length < name_width {
+                        (length..name_width).for_each( |_| name.push( ' ' ) );
+                    }
+
+                    if cfg.pct_first && cfg.columns.contains( &Column::Percent ) {
+                        write!( out, "{} ", fmt_column( &entry, Column::Percent, pct_base, running, cfg.sizes ) )?;
+                    }
+
+                    // draw the tree
+                    let mut tree = String::new();
+                    for open in &open_parents {
+                        if   *open { tree.push_str( &blank ); } 
+                        else       { tree.push_str( &pipe  ); }
+                    }
+                    if   last { tree.push_str( &corner ); op.push( true  ); }
+                    else      { tree.push_str( &tee    ); op.push( false ); }
+
+                    let bar = if cfg.no_bar_flag { None } else {
+                        let color = cfg.gradient.map( |truecolor| {
+                            gradient_color( ( entry.bytes * 100 ).checked_div( pct_base ).unwrap_or( 0 ), truecolor )
+                        } );
+                        Some( fmt_bar( &bytes, widths.bar, cfg.bar_style, color.as_deref() ) )
+                    };
+                    let mut cols = String::new();
+                    for col in cfg.columns.iter().filter( |col| !cfg.pct_first || **col != Column::Percent ) {
+                        cols.push_str( &format!( " {}", fmt_column( &entry, *col, pct_base, running, cfg.sizes ) ) );
+                    }
+
+                    // print it
+                    match ( bar, cfg.layout ) {
+                        ( None, _ )                     => writeln!( out, "{}{}{}", tree, name, cols )?,
+                        ( Some(bar), Layout::NameBar )  => writeln!( out, "{}{} {}{}", tree, name, bar, cols )?,
+                        ( Some(bar), Layout::BarName )  => writeln!( out, "{} {}{}{}", bar, tree, name, cols )?,
+                        ( Some(bar), Layout::BarLast )  => writeln!( out, "{}{}{} {}", tree, name, cols, bar )?,
+                        ( Some(bar), Layout::BarBelow ) => {
+                            writeln!( out, "{}{}{}", tree, name, cols )?;
+
+                            // under the name, between the branches that go on
+                            let mut below = String::new();
+                            for open in &op {
+                                if   *open { below.push_str( &blank ); } 
+                                else       { below.push_str( &pipe  ); }
+                            }
+                            if   entry.entries.is_some() { below.push_str( &pipe  ); }
+                            else                         { below.push_str( &blank ); }
+                            writeln!( out, "{}{}", below, bar )?;
+                        },
+                    }
+                    if entry.entries.is_some() {
+                        let prefix = if self.is_collection() { String::new() } else { format!( "{}/", path ) };
+                        entry.print_entries( out, op, bytes, &prefix, cfg, widths )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn print( self, out : &mut dyn Write, cfg : &Config, twidth : u16 ) -> io::Result<()> {
+
+        // calculate plot widths, the percentage takes its room from the bar
+        let mut cols_width = 1;
+        for col in &cfg.columns {
+            cols_width += match *col {
+                Column::Size    => 14,
+                Column::Percent => 0,
+                Column::Count   => 10,
+                Column::Mtime   => 17,
+                Column::Owner   => 18,
+                Column::Perms   => 11,
+                Column::Cumulative => 5,
+                Column::Alloc   => 28,
+            };
+        }
+        let pct_width       = if cfg.columns.contains( &Column::Percent ) { 5 } else { 0 };
+        let var_width       = ( twidth as usize ).saturating_sub( cols_width );
+        let resolve = |width| match width {
+            ColWidth::Chars(n)   => n.min( var_width ),
+            ColWidth::Percent(p) => var_width * p / 100,
+        };
+        let ( bar_width, tree_name_width ) = match ( cfg.bar_width.map( resolve ), cfg.name_width.map( resolve ) ) {
+            _ if cfg.no_bar_flag      => ( 0, cfg.name_width.map_or( var_width.saturating_sub( pct_width ), resolve ) ),
+            // a bar on a line of its own leaves the whole width to the name
+            ( bar, name ) if cfg.layout == Layout::BarBelow =>
+                ( bar.unwrap_or( var_width * 75 / 100 ), name.unwrap_or( var_width.saturating_sub( pct_width ) ) ),
+            ( Some(bar), Some(name) ) => ( bar, name ),
+            ( Some(bar), None )       => ( bar, var_width.saturating_sub( bar + pct_width ) ),
+            ( None, Some(name) )      => ( var_width.saturating_sub( name + pct_width ), name ),
+            ( None, None )            => ( ( var_width * 75 / 100 ).saturating_sub( pct_width ), var_width * 25 / 100 ),
+        };
+
+        // initalize
+        let     open_parents : Vec<bool> = Vec::new();
+        let     parent_vals  : Vec<u64>  = vec![ self.bytes ];
+
+        // print
+        let name = if cfg.abs_flag && !self.is_collection() { Cow::from( self.path().to_string_lossy().into_owned() ) }
+                   else { Cow::from( self.name() ) };
+        let partial  = if INTERRUPTED.load( Ordering::Relaxed ) { " (partial)" } else { "" };
+        let plus_minus = if cfg.ascii_flag { "+-" } else { "±" };
+        let estimate = estimate_margin( self.bytes ).map_or( String::new(), |pct| format!( " (estimated {}{:.0}%)", plus_minus, pct ) );
+        writeln!( out, "[ {} {}{}{} ]", name, fmt_size_str( self.bytes, cfg.sizes ), partial, estimate )?;
+        *OWNERS.lock().unwrap() = None;
+        self.print_entries( out, open_parents, parent_vals, "", cfg, Widths { bar : bar_width, name : tree_name_width } )
+    }
+}
+
+impl<'a> Node<'a> {
+    // print this node and the edges to its children, returns the node id
+    fn print_dot( self, out : &mut dyn Write, next_id : &mut usize, sizes : Sizes ) -> io::Result<usize> {
+        let id = *next_id;
+        *next_id += 1;
+        writeln!( out, "    n{} [label=\"{}\\n{}\"];", id,
+                  self.name().replace( '\\', "\\\\" ).replace( '"', "\\\"" ),
+                  fmt_size_str( self.bytes, sizes ) )?;
+
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                let child = entry.print_dot( out, next_id, sizes )?;
+                writeln!( out, "    n{} -> n{};", id, child )?;
+            }
+        }
+        Ok( id )
+    }
+}
+
+impl<'a> Node<'a> {
+    // directories are arrays of their own info followed by their children
+    fn print_ncdu( self, out : &mut dyn Write, name : &str ) -> io::Result<()> {
+        // cut at the depth or not, the mode the scan kept tells a directory
+        let is_dir = self.entries.is_some() || file_kind( self.mode ) == 'd';
+
+        // ncdu sums children itself, so directories only report their own size
+        let ( mut size, mut usage ) = ( self.totals.size, self.totals.usage );
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                size  = size.saturating_sub( entry.totals.size );
+                usage = usage.saturating_sub( entry.totals.usage );
+            }
+        }
+        let info = format!( "{{\"name\":{},\"asize\":{},\"dsize\":{},\"dev\":{},\"ino\":{}}}",
+                            json_str( name ), size, usage, self.dev, self.ino );
+
+        if !is_dir {
+            return write!( out, "{}", info );
+        }
+        write!( out, "[{}", info )?;
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                writeln!( out, "," )?;
+                entry.print_ncdu( out, entry.name() )?;
+            }
+        }
+        write!( out, "]" )
+    }
+}
+
+impl<'a> Node<'a> {
+    // children first, like du does
+    fn print_du( self, out : &mut dyn Write, unit : char, sizes : Sizes ) -> io::Result<()> {
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                entry.print_du( out, unit, sizes )?;
+            }
+        }
+        let size = match sizes.units {
+            Units::Block(_) => fmt_size_str( self.bytes, sizes ),
+            _               => fmt_du_size( self.bytes, unit ),
+        };
+        writeln!( out, "{}\t{}", size, self.path().display() )
+    }
+}
+
+impl<'a> Node<'a> {
+    fn visit_files( self, visit : &mut dyn FnMut( Node<'a> ) ) {
+        match self.children() {
+            Some(entries) => for entry in entries {
+                entry.visit_files( visit );
+            },
+            None if !self.is_dir() => visit( self ),
+            None => {},
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    // every entry with what it holds itself, without its children
+    fn visit_own( self, visit : &mut dyn FnMut( Node<'a>, u64, u64 ) ) {
+        let ( mut bytes, mut count ) = ( self.bytes, self.totals.count );
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                bytes = bytes.saturating_sub( entry.bytes );
+                count = count.saturating_sub( entry.totals.count );
+                entry.visit_own( visit );
+            }
+        }
+        visit( self, bytes, count );
+    }
+}
+
+// reports that need every file, so the scan isn't cut at the depth
+fn whole_scan( cfg : &Config ) -> bool {
+    matches!( cfg.format, Format::ByType | Format::ByCategory | Format::AgeHistogram | Format::SizeHistogram |
+                          Format::Duplicates(_) | Format::Stats | Format::ByMount | Format::BiggestDirs(_) )
+}
+
+fn print_scan( trees : &[Node], cfg : &Config, out : &mut dyn Write ) -> io::Result<()> {
+    // ( size, files ) of what the label groups together
+    let mut groups : HashMap<String, (u64, u64)> = HashMap::new();
+    let mut add = |label : String, entry : Node| {
+        let group = groups.entry( label ).or_insert( ( 0, 0 ) );
+        group.0 += entry.bytes;
+        group.1 += entry.totals.count;
+    };
+
+    match cfg.format {
+        Format::ByType => {
+            for root in trees {
+                root.visit_files( &mut |e| {
+                    let ext = file_ext( &e.path() );
+                    add( if ext.is_empty() { "(none)".to_string() } else { format!( ".{}", ext ) }, e )
+                } );
+            }
+            print_groups( groups, cfg, out )
+        },
+        Format::ByCategory => {
+            for root in trees {
+                root.visit_files( &mut |e| add( ext_category( &file_ext( &e.path() ) ).to_string(), e ) );
+            }
+            print_groups( groups, cfg, out )
+        },
+        Format::AgeHistogram => {
+            const DAY : u64 = 24 * 60 * 60;
+            let now = SystemTime::now().duration_since( UNIX_EPOCH ).map( |d| d.as_secs() as i64 ).unwrap_or( 0 );
+            let buckets = [ ( 30 * DAY, "< 30d" ), ( 90 * DAY, "30d-90d" ), ( 365 * DAY, "90d-1y" ), ( u64::MAX, "> 1y" ) ];
+            print_histogram( trees, cfg, out, &buckets, |e| ( now - e.totals.mtime ).max( 0 ) as u64 )
+        },
+        Format::SizeHistogram => {
+            const KIB : u64 = 1024;
+            let buckets = [ ( 4 * KIB, "< 4K" ), ( KIB * KIB, "4K-1M" ), ( 100 * KIB * KIB, "1M-100M" ), ( u64::MAX, "> 100M" ) ];
+            print_histogram( trees, cfg, out, &buckets, |e| e.totals.size )
+        },
+        Format::Duplicates( hash ) => print_duplicates( trees, cfg, out, hash ),
+        Format::ByMount => {
+            // the first mount of a device is the one that isn't bound from another
+            let mut mount_of : HashMap<u64, String> = HashMap::new();
+            for ( dir, fstype ) in mounts() {
+                if let Ok(meta) = fs::metadata( &dir ) {
+                    #[cfg(target_os = "linux")]
+                    let dev = meta.st_dev();
+                    #[cfg(target_os = "macos")]
+                    let dev = meta.dev() as u64;
+                    mount_of.entry( dev ).or_insert( format!( "{} ({})", dir, fstype ) );
+                }
+            }
+            for root in trees {
+                root.visit_own( &mut |e, bytes, count| {
+                    let label = mount_of.get( &e.dev ).cloned().unwrap_or( "(unknown)".to_string() );
+                    let group = groups.entry( label ).or_insert( ( 0, 0 ) );
+                    group.0 += bytes;
+                    group.1 += count;
+                } );
+            }
+            print_groups( groups, cfg, out )
+        },
+        Format::BiggestDirs( n ) => {
+            // every directory is ranked, not only the ones the depth shows, but the paths
+            // given hold all the others and would always lead the list
+            let mut list = Vec::new();
+            for root in trees {
+                for entry in root.children().into_iter().flatten() {
+                    entry.flatten( &mut list );
+                }
+            }
+            list.retain( |e| e.is_dir() );
+            list.sort_by_key( |e| std::cmp::Reverse( e.bytes ) );
+            list.truncate( n );
+            if cfg.reverse {
+                list.reverse();
+            }
+            for e in list {
+                writeln!( out, "{:>13} {}", fmt_size_str( e.bytes, cfg.sizes ), e.path().display() )?;
+            }
+            Ok(())
+        },
+        Format::Stats => {
+            writeln!( out, "{:>13} {:>13} {:>9} path", "average", "median", "files" )?;
+            for root in trees {
+                root.print_stats( out, cfg, depth_for( cfg, &root.path() ) )?;
+            }
+            Ok(())
+        },
+        _ => Ok(()),
+    }
+}
+
+impl<'a> Node<'a> {
+    // of the files anywhere below each directory, parents before children
+    fn print_stats( self, out : &mut dyn Write, cfg : &Config, depth : Option<u8> ) -> io::Result<()> {
+        let entries = match self.children() {
+            Some(entries) => entries,
+            None              => return Ok(()),
+        };
+        let mut sizes = Vec::new();
+        self.visit_files( &mut |e| if e.totals.count > 0 { sizes.push( e.bytes ) } );
+        sizes.sort_unstable();
+
+        let files = sizes.len() as u64;
+        let avg    = sizes.iter().sum::<u64>().checked_div( files ).unwrap_or( 0 );
+        let mid    = sizes.len() / 2;
+        let median = match sizes.len() {
+            0               => 0,
+            n if n % 2 == 0 => ( sizes[mid - 1] + sizes[mid] ) / 2,
+            _               => sizes[mid],
+        };
+        writeln!( out, "{:>13} {:>13} {:>9} {}", fmt_size_str( avg, cfg.sizes ), fmt_size_str( median, cfg.sizes ),
+                  files, self.path().display() )?;
+
+        let depth = depth.map( |d| d - 1 );
+        if depth != Some( 0 ) {
+            for entry in entries {
+                entry.print_stats( out, cfg, depth )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// sets of regular files of the same size, and contents when hashing, largest waste first
+fn print_duplicates( trees : &[Node], cfg : &Config, out : &mut dyn Write, hash : bool ) -> io::Result<()> {
+    let mut by_size : HashMap<u64, Vec<Node>> = HashMap::new();
+    for root in trees {
+        // regular files as they were scanned, not links or what a listing made up
+        root.visit_files( &mut |e| if e.totals.size > 0 && e.totals.count > 0 && e.mode != 0 && file_kind( e.mode ) == 'f' {
+            by_size.entry( e.totals.size ).or_default().push( e );
+        } );
+    }
+
+    // ( size, path ) of the files, only the ones that may have duplicates get their path rebuilt
+    let mut sets : Vec<Vec<( u64, PathBuf )>> = Vec::new();
+    for ( _, files ) in by_size {
+        if files.len() < 2 { continue }
+        let files : Vec<( u64, PathBuf )> = files.into_iter().map( |e| ( e.bytes, e.path() ) ).collect();
+        if !hash {
+            sets.push( files );
+            continue;
+        }
+        let mut by_hash : HashMap<u64, Vec<( u64, PathBuf )>> = HashMap::new();
+        for ( bytes, path ) in files {
+            match hash_file( &path ) {
+                Ok(h)    => by_hash.entry( h ).or_default().push( ( bytes, path ) ),
+                Err(err) => print_io_error( &path, err ),
+            }
+        }
+        sets.extend( by_hash.into_values().filter( |set| set.len() > 1 ) );
+    }
+
+    let waste = |set : &Vec<( u64, PathBuf )>| set[0].0 * ( set.len() as u64 - 1 );
+    sets.sort_by( |a, b| waste( b ).cmp( &waste( a ) ).then_with( || a[0].1.cmp( &b[0].1 ) ) );
+    let mut total = 0;
+    for set in &mut sets {
+        set.sort_by( |a, b| a.1.cmp( &b.1 ) );
+        total += waste( set );
+        writeln!( out, "[ {} x {}, {} reclaimable ]", set.len(), fmt_size_str( set[0].0, cfg.sizes ),
+                  fmt_size_str( waste( set ), cfg.sizes ) )?;
+        for ( _, path ) in set.iter() {
+            writeln!( out, "{}", path.display() )?;
+        }
+    }
+    writeln!( out, "{} reclaimable in {} sets", fmt_size_str( total, cfg.sizes ), sets.len() )
+}
+
+fn hash_file( path : &Path ) -> io::Result<u64> {
+    use std::hash::Hasher;
+    let mut file   = fs::File::open( path )?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    let mut buf    = vec![ 0u8; 64 * 1024 ];
+    loop {
+        let n = file.read( &mut buf )?;
+        if n == 0 { break }
+        hasher.write( &buf[..n] );
+    }
+    Ok( hasher.finish() )
+}
+
+// ( size, files ) of each root in the first bucket whose limit the key is under
+fn print_histogram<F>( trees : &[Node], cfg : &Config, out : &mut dyn Write, buckets : &[( u64, &str )], key : F )
+                       -> io::Result<()> where F : Fn( &Entry ) -> u64 {
+    for root in trees {
+        let mut hist = vec![ ( 0, 0 ); buckets.len() ];
+        root.visit_files( &mut |e| {
+            let k = key( &e );
+            let i = buckets.iter().position( |&( max, _ )| k < max ).unwrap_or( buckets.len() - 1 );
+            hist[i].0 += e.bytes;
+            hist[i].1 += e.totals.count;
+        } );
+        writeln!( out, "[ {} {} ]", root.name(), fmt_size_str( root.bytes, cfg.sizes ) )?;
+        for ( &( _, label ), ( bytes, count ) ) in buckets.iter().zip( hist ) {
+            writeln!( out, "{:>13} {:>9} {}", fmt_size_str( bytes, cfg.sizes ), count, label )?;
+        }
+    }
+    Ok(())
+}
+
+// coarse kinds of files, by extension
+fn ext_category( ext : &str ) -> &'static str {
+    match ext {
+        "mp4" | "mkv" | "avi" | "mov" | "wmv" | "webm" | "flv" | "m4v" | "mpg" | "mpeg" => "video",
+        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "tif" | "tiff" | "webp" | "svg" | "heic" | "raw" | "ico" => "images",
+        "mp3" | "flac" | "wav" | "ogg" | "m4a" | "aac" | "opus" | "wma" => "audio",
+        "zip" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "7z" | "rar" | "iso" | "dmg" | "deb" | "rpm" => "archives",
+        "c" | "h" | "cc" | "cpp" | "hpp" | "rs" | "go" | "py" | "js" | "ts" | "java" | "kt" | "rb" | "php" |
+        "sh" | "pl" | "swift" | "cs" | "o" | "a" | "so" | "class" | "jar" | "json" | "toml" | "yaml" | "yml" | "xml" => "code",
+        "pdf" | "doc" | "docx" | "odt" | "xls" | "xlsx" | "ods" | "ppt" | "pptx" | "odp" | "txt" | "md" | "rtf" |
+        "csv" | "epub" | "html" | "htm" => "documents",
+        _ => "other",
+    }
+}
+
+// ( size, files ) by label, largest first
+fn print_groups( groups : HashMap<String, (u64, u64)>, cfg : &Config, out : &mut dyn Write ) -> io::Result<()> {
+    let mut list : Vec<_> = groups.into_iter().collect();
+    list.sort_by( |a, b| {
+        let ord = ( b.1 ).0.cmp( &( a.1 ).0 ).then_with( || a.0.cmp( &b.0 ) );
+        if cfg.reverse { ord.reverse() } else { ord }
+    } );
+    for ( label, ( bytes, count ) ) in list {
+        writeln!( out, "{:>13} {:>9} {}", fmt_size_str( bytes, cfg.sizes ), count, label )?;
+    }
+    Ok(())
+}
+
+impl<'a> Node<'a> {
+    // paths are written verbatim, they may contain anything but NUL
+    fn print0( self, out : &mut dyn Write ) -> io::Result<()> {
+        out.write_all( self.path().as_os_str().as_bytes() )?;
+        write!( out, "\0{}\0", self.bytes )?;
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                entry.print0( out )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Node<'a> {
+    fn write_snapshot( self, out : &mut dyn Write ) -> io::Result<()> {
+        write_bytes( out, self.tree.names.get( self.name ) )?;
+        write_bytes( out, self.path().as_os_str().as_bytes() )?;
+        write_bytes( out, self.color.map_or( "", Color::code ).as_bytes() )?;
+        write_bytes( out, self.target().map_or( &[][..], |target| target.as_os_str().as_bytes() ) )?;
+        let t = &self.totals;
+        for n in &[ t.size, t.usage, t.count, t.dirs, t.mtime as u64, self.dev, self.ino,
+                    self.mode as u64, self.uid as u64, self.gid as u64 ] {
+            write_u64( out, *n )?;
+        }
+        match self.children() {
+            None          => out.write_all( &[0] ),
+            Some(entries) => {
+                out.write_all( &[1] )?;
+                write_u64( out, entries.len() as u64 )?;
+                for entry in entries {
+                    entry.write_snapshot( out )?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Tree {
+    // paths below the roots follow from the names, like in a scan
+    fn read_snapshot( &mut self, input : &mut dyn Read, cfg : &Config, root : bool ) -> io::Result<Entry> {
+        let name  = self.names.add( &read_bytes( input )? );
+        let path  = read_bytes( input )?;
+        let path  = if root { self.names.add( &path ) } else { NO_NAME };
+        let color = String::from_utf8_lossy( &read_bytes( input )? ).into_owned();
+        let target = read_bytes( input )?;
+        let target = if target.is_empty() { NO_NAME } else { self.names.add( &target ) };
+        let totals = Totals {
+            size  : read_u64( input )?,
+            usage : read_u64( input )?,
+            count : read_u64( input )?,
+            dirs  : read_u64( input )?,
+            mtime : read_u64( input )? as i64,
+        };
+        let dev   = read_u64( input )?;
+        let ino   = read_u64( input )?;
+        let mode  = read_u64( input )? as u32;
+        let uid   = read_u64( input )? as u32;
+        let gid   = read_u64( input )? as u32;
+
+        let mut flag = [0];
+        input.read_exact( &mut flag )?;
+        let entries = if flag[0] == 1 {
+            let len = read_u64( input )?;
+            let mut vec = Vec::new();
+            for _ in 0..len {
+                vec.push( self.read_snapshot( input, cfg, false )? );
+            }
+            Some( self.place( vec ) )
+        } else { None };
+
+        Ok( Entry {
+            name,
+            path,
+            parent : NO_PARENT,
+            bytes : measure( &totals, cfg ),
+            totals,
+            dev,
+            ino,
+            mode,
+            uid,
+            gid,
+            target,
+            color : if color.is_empty() { None } else { Some( Color::new( &color ) ) },
+            aggregated : false,
+            entries,
+        } )
+    }
+}
+
+// .gz and .zst files are compressed
+fn write_snapshot( roots : &[Node], file : &Path ) -> io::Result<()> {
+    let mut out = io::BufWriter::new( fs::File::create( file )? );
+    match file.extension().and_then( |e| e.to_str() ) {
+        #[cfg(feature = "gzip")]
+        Some("gz")  => {
+            let mut out = flate2::write::GzEncoder::new( out, flate2::Compression::default() );
+            write_roots( roots, &mut out )?;
+            out.finish()?.flush()
+        },
+        #[cfg(feature = "zstd")]
+        Some("zst") => {
+            let mut out = zstd::Encoder::new( out, 0 )?;
+            write_roots( roots, &mut out )?;
+            out.finish()?.flush()
+        },
+        _           => {
+            write_roots( roots, &mut out )?;
+            out.flush()
+        },
+    }
+}
+
+// the header has what the scan found, so the next one can tell its progress without reading the rest
+fn write_roots( roots : &[Node], out : &mut dyn Write ) -> io::Result<()> {
+    out.write_all( SNAPSHOT )?;
+    write_u64( out, roots.iter().map( |root| root.totals.count + root.totals.dirs ).sum() )?;
+    write_u64( out, roots.len() as u64 )?;
+    for root in roots {
+        root.write_snapshot( out )?;
+    }
+    Ok(())
+}
+
+fn read_snapshot( file : &Path, cfg : &Config ) -> io::Result<Tree> {
+    let ( mut input, _ ) = open_snapshot( file )?;
+    let len = read_u64( &mut input )?;
+    let mut tree  = Tree::default();
+    let mut roots = Vec::new();
+    for _ in 0..len {
+        roots.push( tree.read_snapshot( &mut input, cfg, true )? );
+    }
+    tree.plant( roots, cfg );
+    Ok( tree )
+}
+
+// the rest of the snapshot after its header, and the files and directories it holds
+// compressed snapshots are recognized by their magic, whatever the file is called
+fn open_snapshot( file : &Path ) -> io::Result<( Box<dyn Read>, u64 )> {
+    let mut input = io::BufReader::new( fs::File::open( file )? );
+    let head = io::BufRead::fill_buf( &mut input )?.to_vec();
+    let mut input : Box<dyn Read> = if head.starts_with( &[ 0x1f, 0x8b ] ) {
+        #[cfg(feature = "gzip")]
+        { Box::new( io::BufReader::new( flate2::bufread::MultiGzDecoder::new( input ) ) ) }
+        #[cfg(not(feature = "gzip"))]
+        { return Err( io::Error::new( io::ErrorKind::InvalidData, "gzip support not built in" ) ) }
+    } else if head.starts_with( &[ 0x28, 0xb5, 0x2f, 0xfd ] ) {
+        #[cfg(feature = "zstd")]
+        { Box::new( io::BufReader::new( zstd::Decoder::with_buffer( input )? ) ) }
+        #[cfg(not(feature = "zstd"))]
+        { return Err( io::Error::new( io::ErrorKind::InvalidData, "zstd support not built in" ) ) }
+    } else {
+        Box::new( input )
+    };
+    let mut magic = [0; 7];
+    input.read_exact( &mut magic )?;
+    if magic != SNAPSHOT {
+        return Err( io::Error::new( io::ErrorKind::InvalidData, "not a dutree snapshot" ) );
+    }
+    let entries = read_u64( &mut input )?;
+    Ok( ( input, entries ) )
+}
+
+// the entries of a directory, whether each is a directory if known, and its stat if taken
+type Listing = Vec<(PathBuf, Option<bool>, Option<Stat>)>;
+
+// a directory as a scan listed it, with the stats of what is known not to be a directory
+struct CachedDir {
+    stat : Stat,
+    list : Listing,     // by name
+    seen : bool,                                        // used by this scan
+}
+
+struct ScanCache {
+    cwd     : PathBuf,  // the directories are kept by their absolute path
+    started : i64,      // what changed since might have changed while being listed
+    dirs    : HashMap<PathBuf, CachedDir>,
+    entries : usize,    // listed in dirs, up to CACHE_LIMIT
+    changed : bool,     // since it was read
+}
+
+// listed entries kept for each scanned path, some 50 MB of cache at most
+const CACHE_LIMIT : usize = 1 << 19;
+
+// a file for each scanned path in the dutree directory of the cache home
+fn cache_file( home : &Path, root : &Path ) -> PathBuf {
+    // the name of a path stays the same from one build to the next
+    home.join( "dutree" ).join( format!( "scan-{:016x}", fnv1a( root.as_os_str().as_bytes() ) ) )
+}
+
+fn cache_key( cwd : &Path, path : &Path ) -> PathBuf {
+    cwd.join( path ).components().collect()
+}
+
+// only the records of the scanned paths are read, a cache that can't be read is started over
+fn load_cache( cfg : &Config ) {
+    let cwd = match env::current_dir() {
+        Ok(cwd) => cwd,
+        Err(_)  => return,
+    };
+    let mut dirs = HashMap::new();
+    if cfg.cache == CacheMode::Use {
+        for path in &cfg.paths {
+            let root  = cache_key( &cwd, path );
+            let found = cfg.cache_home.as_ref().and_then( |home| fs::File::open( cache_file( home, &root ) ).ok() )
+                                           .and_then( |file| read_cache( &mut io::BufReader::new( file ) ).ok() )
+                                           .unwrap_or_default();
+            dirs.extend( found.into_iter().filter( |dir| dir.0.starts_with( &root ) ) );
+        }
+    }
+    let entries = dirs.values().map( |dir : &CachedDir| dir.list.len() ).sum();
+    let started = SystemTime::now().duration_since( UNIX_EPOCH ).map( |d| d.as_secs() as i64 ).unwrap_or( 0 );
+    *CACHE.lock().unwrap() = Some( ScanCache { cwd, started, dirs, entries, changed : cfg.cache == CacheMode::Refresh } );
+}
+
+// directories below the scanned paths that the scan didn't get to are gone, or changed above
+fn save_cache( cfg : &Config ) -> io::Result<()> {
+    let mut cache = match CACHE.lock().unwrap().take() {
+        Some(cache) => cache,
+        None        => return Ok(()),
+    };
+    let roots : Vec<PathBuf> = cfg.paths.iter().map( |path| cache_key( &cache.cwd, path ) ).collect();
+    if !INTERRUPTED.load( Ordering::Relaxed ) {
+        let before = cache.dirs.len();
+        cache.dirs.retain( |_, dir| dir.seen );
+        cache.changed |= cache.dirs.len() != before;
+    }
+    // a scan that found everything as it was leaves the files as they are
+    if !cache.changed { return Ok(()) }
+
+    for root in &roots {
+        let file = match cfg.cache_home {
+            Some(ref home) => cache_file( home, root ),
+            None           => return Ok(()),
+        };
+        let dirs : Vec<(&PathBuf, &CachedDir)> = cache.dirs.iter().filter( |&( path, _ )| path.starts_with( root ) ).collect();
+
+        // written aside first, so that another scan never reads half of it
+        fs::create_dir_all( file.parent().unwrap() )?;
+        let temp = file.with_extension( std::process::id().to_string() );
+        let mut out = io::BufWriter::new( fs::File::create( &temp )? );
+        write_cache( &dirs, &mut out )?;
+        out.flush()?;
+        drop( out );
+        fs::rename( &temp, &file )?;
+    }
+    Ok(())
+}
+
+fn caching( cfg : &Config ) -> bool {
+    !cfg.follow_flag && CACHE.lock().unwrap().is_some()
+}
+
+// the cache is only read for a directory with the same inode and mtime, unless no stat is given to check
+// its files are taken as they were too, a file rewritten in place doesn't change the mtime of its directory
+fn cached_dir( path : &Path, stat : Option<&Stat>, cfg : &Config ) -> Option<(Stat, Listing)> {
+    if cfg.cache != CacheMode::Use { return None }
+    let mut cache = CACHE.lock().unwrap();
+    let cache = cache.as_mut()?;
+    let dir = cache.dirs.get_mut( &cache_key( &cache.cwd, path ) )?;
+    if let Some(stat) = stat {
+        if ( dir.stat.dev, dir.stat.ino, dir.stat.mtime ) != ( stat.dev, stat.ino, stat.mtime ) { return None }
+    }
+    dir.seen = true;
+    let list = dir.list.iter().map( |&( ref name, is_dir, stat )| ( path.join( name ), is_dir, stat ) ).collect();
+    Some( ( dir.stat, list ) )
+}
+
+fn cache_dir( path : &Path, stat : &Stat, list : Listing ) {
+    if let Some(ref mut cache) = *CACHE.lock().unwrap() {
+        // a change in the second the scan started wouldn't show in the mtime
+        if stat.mtime >= cache.started { return }
+        let key  = cache_key( &cache.cwd, path );
+        let kept = cache.entries - cache.dirs.get( &key ).map_or( 0, |dir| dir.list.len() );
+        if kept + list.len() > CACHE_LIMIT { return }
+
+        let list : Listing = list.into_iter().map( |( path, is_dir, stat )| {
+            ( path.file_name().map( PathBuf::from ).unwrap_or_default(), is_dir, stat )
+        } ).collect();
+        cache.entries = kept + list.len();
+        cache.changed = true;
+        cache.dirs.insert( key, CachedDir { stat : *stat, list, seen : true } );
+    }
+}
+
+fn write_cache( dirs : &[(&PathBuf, &CachedDir)], out : &mut dyn Write ) -> io::Result<()> {
+    out.write_all( CACHE_MAGIC )?;
+    write_u64( out, dirs.len() as u64 )?;
+    for &( path, dir ) in dirs {
+        write_bytes( out, path.as_os_str().as_bytes() )?;
+        write_stat( out, &dir.stat )?;
+        write_u64( out, dir.list.len() as u64 )?;
+        for &( ref name, is_dir, stat ) in &dir.list {
+            write_bytes( out, name.as_os_str().as_bytes() )?;
+            out.write_all( &[ is_dir.map_or( 0, |d| d as u8 + 1 ) ] )?;
+            match stat {
+                None       => out.write_all( &[0] )?,
+                Some(stat) => {
+                    out.write_all( &[1] )?;
+                    write_stat( out, &stat )?;
+                },
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_cache( input : &mut dyn Read ) -> io::Result<HashMap<PathBuf, CachedDir>> {
+    let mut magic = [0; 8];
+    input.read_exact( &mut magic )?;
+    if magic != CACHE_MAGIC {
+        return Err( io::Error::new( io::ErrorKind::InvalidData, "not a dutree cache" ) );
+    }
+    let mut flags = [0; 2];
+    let mut dirs = HashMap::new();
+    for _ in 0..read_u64( input )? {
+        let path = PathBuf::from( OsStr::from_bytes( &read_bytes( input )? ) );
+        let stat = read_stat( input )?;
+        let mut list = Vec::new();
+        for _ in 0..read_u64( input )? {
+            let name = PathBuf::from( OsStr::from_bytes( &read_bytes( input )? ) );
+            input.read_exact( &mut flags )?;
+            let is_dir = match flags[0] { 0 => None, d => Some( d == 2 ) };
+            let stat   = if flags[1] == 1 { Some( read_stat( input )? ) } else { None };
+            list.push( ( name, is_dir, stat ) );
+        }
+        dirs.insert( path, CachedDir { stat, list, seen : false } );
+    }
+    Ok( dirs )
+}
+
+fn write_stat( out : &mut dyn Write, stat : &Stat ) -> io::Result<()> {
+    for n in &[ stat.dev, stat.ino, stat.nlink, stat.is_dir as u64, stat.mode as u64, stat.uid as u64, stat.gid as u64,
+                stat.size, stat.blocks, stat.mtime as u64 ] {
+        write_u64( out, *n )?;
+    }
+    Ok(())
+}
+
+fn read_stat( input : &mut dyn Read ) -> io::Result<Stat> {
+    Ok( Stat {
+        dev    : read_u64( input )?,
+        ino    : read_u64( input )?,
+        nlink  : read_u64( input )?,
+        is_dir : read_u64( input )? == 1,
+        mode   : read_u64( input )? as u32,
+        uid    : read_u64( input )? as u32,
+        gid    : read_u64( input )? as u32,
+        size   : read_u64( input )?,
+        blocks : read_u64( input )?,
+        mtime  : read_u64( input )? as i64,
+        link   : false,     // links aren't followed when caching
+    } )
+}
+
+// rebuild trees from 'du -ab' lines, where every directory follows its contents
+fn read_du( file : &Path, cfg : &Config ) -> io::Result<Tree> {
+    let stdin = io::stdin();
+    let input : Box<dyn io::BufRead> = if file.as_os_str() == "-" {
+        Box::new( stdin.lock() )
+    } else {
+        Box::new( io::BufReader::new( fs::File::open( file )? ) )
+    };
+
+    let mut lines : Vec<(PathBuf, u64)> = Vec::new();
+    for ( n, line ) in io::BufRead::split( input, b'\n' ).enumerate() {
+        let line = line?;
+        let mut fields = line.splitn( 2, |&c| c == b'\t' );
+        let size = fields.next().and_then( |f| std::str::from_utf8( f ).ok() )
+                                .and_then( |f| f.trim().parse::<u64>().ok() );
+        match ( size, fields.next() ) {
+            ( Some(size), Some(path) ) => lines.push( ( PathBuf::from( OsStr::from_bytes( path ) ), size ) ),
+            _                          => eprintln!( "Couldn't parse line {} of {}", n + 1, file.display() ),
+        }
+    }
+
+    // the lines below each directory in the order of the listing, whatever has no parent is a root
+    let mut index : HashMap<&Path, usize> = HashMap::new();
+    for ( i, ( path, _ ) ) in lines.iter().enumerate() {
+        index.insert( path, i );
+    }
+    let mut children : Vec<Vec<usize>> = vec![ Vec::new(); lines.len() ];
+    let mut roots = Vec::new();
+    for ( i, ( path, _ ) ) in lines.iter().enumerate() {
+        if index[path.as_path()] != i { continue }  // repeated line
+        match path.parent().and_then( |p| index.get( p ) ) {
+            Some(&parent) => children[parent].push( i ),
+            None          => roots.push( i ),
+        }
+    }
+
+    let mut tree = Tree::default();
+    let roots = roots.into_iter().map( |i| du_entry( &mut tree, &lines, &children, i, true, cfg ) ).collect();
+    tree.plant( roots, cfg );
+    Ok( tree )
+}
+
+// the entry of a line, what the lines below it hold is placed before it
+fn du_entry( tree : &mut Tree, lines : &[(PathBuf, u64)], children : &[Vec<usize>], i : usize, root : bool, cfg : &Config ) -> Entry {
+    let ( ref path, size ) = lines[i];
+    let mut totals = Totals { size, usage: size, count: 1, dirs: 0, mtime: 0 };
+    let entries = if children[i].is_empty() { None } else {
+        // counted as a file until its contents showed up
+        totals.count = 0;
+        totals.dirs  = 1;
+        let vec : Vec<Entry> = children[i].iter().map( |&child| du_entry( tree, lines, children, child, false, cfg ) ).collect();
+        for entry in &vec {
+            totals.count += entry.totals.count;
+            totals.dirs  += entry.totals.dirs;
+        }
+        Some( tree.place( vec ) )
+    };
+    let color = if cfg.color_flag {
+        if path.symlink_metadata().is_ok() {
+            color_from_path( path, &cfg.color_dict )
+        } else if entries.is_some() {
+            cfg.color_dict.get( "di" ).map( |c| c.as_str() )
+        } else {
+            color_from_ext( path, &cfg.color_dict ).or( cfg.color_dict.get( "fi" ).map( |c| c.as_str() ) )
+        }.map( Color::new )
+    } else { None };
+
+    let ( name, path ) = tree.add_names( path, root );
+    Entry {
+        name,
+        path,
+        parent  : NO_PARENT,
+        bytes   : measure( &totals, cfg ),
+        totals,
+        dev     : 0,
+        ino     : 0,
+        mode    : 0,
+        uid     : 0,
+        gid     : 0,
+        target  : NO_NAME,
+        color,
+        aggregated : false,
+        entries,
+    }
+}
+
+fn write_u64( out : &mut dyn Write, n : u64 ) -> io::Result<()> {
+    out.write_all( &n.to_le_bytes() )
+}
+
+fn read_u64( input : &mut dyn Read ) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact( &mut buf )?;
+    Ok( u64::from_le_bytes( buf ) )
+}
+
+fn write_bytes( out : &mut dyn Write, bytes : &[u8] ) -> io::Result<()> {
+    write_u64( out, bytes.len() as u64 )?;
+    out.write_all( bytes )
+}
+
+fn read_bytes( input : &mut dyn Read ) -> io::Result<Vec<u8>> {
+    let len = read_u64( input )?;
+    let mut bytes = Vec::new();
+    input.take( len ).read_to_end( &mut bytes )?;
+    if bytes.len() as u64 != len {
+        return Err( io::Error::new( io::ErrorKind::UnexpectedEof, "truncated snapshot" ) );
+    }
+    Ok( bytes )
+}
+
+impl<'a> Node<'a> {
+    fn print_template( self, out : &mut dyn Write, template : &str, depth : usize,
+                       parent_bytes : u64, pct_root : bool, sizes : Sizes ) -> io::Result<()> {
+        let percent = ( self.bytes * 100 ).checked_div( parent_bytes ).unwrap_or( 0 );
+        let line = fmt_template( template, |key| match key {
+            "path"    => Some( self.path().to_string_lossy().into_owned() ),
+            "name"    => Some( self.name().to_string() ),
+            "size"    => Some( fmt_size_str( self.bytes, sizes ) ),
+            "bytes"   => Some( self.bytes.to_string() ),
+            "percent" => Some( format!( "{}%", percent ) ),
+            "depth"   => Some( depth.to_string() ),
+            _         => None,
+        } );
+        writeln!( out, "{}", line )?;
+
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                let base = if pct_root { parent_bytes } else { self.bytes };
+                entry.print_template( out, template, depth + 1, base, pct_root, sizes )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// replace {key} placeholders in one pass, unknown keys are left untouched
+fn fmt_template<F>( template : &str, value : F ) -> String where F : Fn( &str ) -> Option<String> {
+    let mut line = String::with_capacity( template.len() );
+    let mut rest = template;
+    while let Some( start ) = rest.find( '{' ) {
+        line.push_str( &rest[..start] );
+        rest = &rest[start..];
+        match rest.find( '}' ).and_then( |end| value( &rest[1..end] ).map( |v| ( end, v ) ) ) {
+            Some( ( end, v ) ) => { line.push_str( &v ); rest = &rest[end + 1..]; },
+            None               => { line.push( '{' );    rest = &rest[1..];       },
+        }
+    }
+    line.push_str( rest );
+    line
+}
+
+// du rounds sizes up to the next unit
+fn fmt_du_size( bytes : u64, unit : char ) -> String {
+    match unit {
+        'b' => bytes.to_string(),
+        'k' => bytes.div_ceil( 1024 ).to_string(),
+        _   => {
+            if bytes < 1024 { return bytes.to_string() }
+            let units = [ 'K', 'M', 'G', 'T', 'P', 'E' ];
+            let mut val = bytes as f64 / 1024.0;
+            let mut i   = 0;
+            while val >= 1024.0 && i < units.len() - 1 {
+                val /= 1024.0;
+                i   += 1;
+            }
+            let tenths = ( val * 10.0 ).ceil() / 10.0;
+            if tenths < 10.0 {
+                format!( "{:.1}{}", tenths, units[i] )
+            } else if val.ceil() < 1024.0 || i == units.len() - 1 {
+                format!( "{}{}", val.ceil(), units[i] )
+            } else {
+                format!( "1.0{}", units[i + 1] )
+            }
+        },
+    }
+}
+
+impl<'a> Node<'a> {
+    // same layout as 'tree -J --du -s', counts are ( directories, files )
+    fn print_json( self, out : &mut dyn Write, indent : usize, name : &str,
+                   counts : &mut (u64, u64) ) -> io::Result<()> {
+        // the type is the one the scan saw, also for loaded or imported trees
+        let is_link  = self.is_link();
+        let is_dir   = self.is_dir() || file_kind( self.mode ) == 'd';
+        let kind     = if is_link { "link" } else if is_dir { "directory" } else { "file" };
+        if indent > 1 {
+            if is_dir && !is_link { counts.0 += 1 } else { counts.1 += 1 }
+        }
+
+        write!( out, "{}{{\"type\":\"{}\",\"name\":{}", "  ".repeat( indent ), kind, json_str( name ) )?;
+        if is_link {
+            let target = self.target().unwrap_or( Path::new( "" ) );
+            write!( out, ",\"target\":{}", json_str( &target.to_string_lossy() ) )?;
+        }
+        write!( out, ",\"size\":{}", self.bytes )?;
+
+        if let Some(entries) = self.children() {
+            writeln!( out, ",\"contents\":[" )?;
+            let len = entries.len();
+            for ( i, entry ) in entries.enumerate() {
+                entry.print_json( out, indent + 1, entry.name(), counts )?;
+                writeln!( out, "{}", if i + 1 < len { "," } else { "" } )?;
+            }
+            write!( out, "{}]}}", "  ".repeat( indent ) )
+        } else {
+            write!( out, "}}" )
+        }
+    }
+}
+
+fn json_str( text : &str ) -> String {
+    let mut json = String::with_capacity( text.len() + 2 );
+    json.push( '"' );
+    for c in text.chars() {
+        match c {
+            '"'  => json.push_str( "\\\"" ),
+            '\\' => json.push_str( "\\\\" ),
+            c if ( c as u32 ) < 0x20 => json.push_str( &format!( "\\u{:04x}", c as u32 ) ),
+            c    => json.push( c ),
+        }
+    }
+    json.push( '"' );
+    json
+}
+
+// quote fields that contain the separator, quotes or line breaks
+fn csv_field( field : &str, sep : char ) -> String {
+    if field.contains( [ sep, '"', '\n', '\r' ] ) {
+        format!( "\"{}\"", field.replace( "\"", "\"\"" ) )
+    } else {
+        field.to_string()
+    }
+}
+
+// top level entries of a scan, skipping the <collection> of multiple paths
+fn roots( entry : Node ) -> Vec<Node> {
+    match entry.children() {
+        Some(entries) if entry.is_collection() => entries.collect(),
+        _                                      => vec![ entry ],
+    }
+}
+
+impl<'a> Node<'a> {
+    // draw this entry as a rectangle and lay out its children inside it
+    fn svg_rects( self, svg : &mut String, x : f64, y : f64, w : f64, h : f64 ) {
+        let fill = self.color.and_then( |c| svg_color( c.code() ) ).unwrap_or( "#a0a0a0".to_string() );
+        svg.push_str( &format!(
+            "<g><title>{} {}</title><rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" \
+             fill=\"{}\" fill-opacity=\"0.6\" stroke=\"#ffffff\"/>",
+            xml_escape( self.name() ), fmt_size_str( self.bytes, Sizes { units: Units::Binary, precision: 2, group: None } ), x, y, w, h, fill ) );
+        if w > 40.0 && h > 14.0 {
+            let name : String = self.name().chars().take( ( w / 6.0 ) as usize - 1 ).collect();
+            svg.push_str( &format!( "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+                                    x + 3.0, y + 11.0, xml_escape( &name ) ) );
+        }
+        svg.push_str( "</g>\n" );
+
+        // children go below the label, leaving a small border
+        let ( pad, header ) = ( 2.0, 14.0 );
+        if w <= 2.0 * pad || h <= header + pad || self.bytes == 0 { return }
+        if let Some(entries) = self.children() {
+            let mut children : Vec<Node> = entries.filter( |e| e.bytes > 0 ).collect();
+            children.sort_by_key( |e| std::cmp::Reverse( e.bytes ) );
+
+            let ( x, y, w, h ) = ( x + pad, y + header, w - 2.0 * pad, h - header - pad );
+            let scale = w * h / self.bytes as f64;
+            let areas : Vec<f64> = children.iter().map( |e| e.bytes as f64 * scale ).collect();
+            for ( entry, r ) in children.iter().zip( squarify( &areas, x, y, w, h ) ) {
+                entry.svg_rects( svg, r.0, r.1, r.2, r.3 );
+            }
+        }
+    }
+}
+
+fn write_treemap( entry : Node, file : &Path ) -> io::Result<()> {
+    let mut svg = format!( "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" \
+                            viewBox=\"0 0 {0} {1}\" font-family=\"monospace\" font-size=\"10\">\n",
+                           SVG_WIDTH, SVG_HEIGHT );
+    entry.svg_rects( &mut svg, 0.0, 0.0, SVG_WIDTH, SVG_HEIGHT );
+    svg.push_str( "</svg>\n" );
+    fs::write( file, svg )
+}
+
+impl<'a> Node<'a> {
+    fn prometheus_metrics( self, prom : &mut String ) {
+        let path = self.path().to_string_lossy()
+                       .replace( '\\', "\\\\" ).replace( '"', "\\\"" ).replace( '\n', "\\n" );
+        prom.push_str( &format!( "dutree_bytes{{path=\"{}\"}} {}\n", path, self.bytes ) );
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                entry.prometheus_metrics( prom );
+            }
+        }
+    }
+}
+
+// node_exporter textfile format, renamed into place so partial files are never scraped
+fn write_prometheus( roots : &[Node], file : &Path ) -> io::Result<()> {
+    let mut prom = String::new();
+    prom.push_str( "# HELP dutree_bytes Size of files and directories as reported by dutree.\n" );
+    prom.push_str( "# TYPE dutree_bytes gauge\n" );
+    for root in roots {
+        root.prometheus_metrics( &mut prom );
+    }
+
+    let mut tmp = file.as_os_str().to_os_string();
+    tmp.push( ".tmp" );
+    fs::write( &tmp, prom )?;
+    fs::rename( &tmp, file )
+}
+
+impl<'a> Node<'a> {
+    // ( path, depth, size, usage, percent of parent ) for every entry
+    fn rows( self, depth : i32, parent_bytes : u64, rows : &mut Vec<(String, i32, u64, u64, f64)> ) {
+        let percent = if parent_bytes > 0 {
+            self.bytes as f64 * 100.0 / parent_bytes as f64
+        } else { 0.0 };
+        rows.push( ( self.path().to_string_lossy().into_owned(), depth, self.totals.size, self.totals.usage, percent ) );
+
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                entry.rows( depth + 1, self.bytes, rows );
+            }
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet( roots : &[Node], file : &Path ) -> Result<(), parquet::errors::ParquetError> {
+    use parquet::data_type::{ ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type };
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+
+    let mut rows = Vec::new();
+    for root in roots {
+        root.rows( 0, root.bytes, &mut rows );
+    }
+
+    let schema = parse_message_type( "message dutree {
+        REQUIRED BYTE_ARRAY path (UTF8);
+        REQUIRED INT32      depth;
+        REQUIRED INT64      size;
+        REQUIRED INT64      usage;
+        REQUIRED DOUBLE     percent;
+    }" )?;
+    let props  = WriterProperties::builder().build();
+    let mut writer    = SerializedFileWriter::new( fs::File::create( file )?, Arc::new( schema ), Arc::new( props ) )?;
+    let mut row_group = writer.next_row_group()?;
+
+    let mut n = 0;
+    while let Some( mut column ) = row_group.next_column()? {
+        match n {
+            0 => { let v : Vec<ByteArray> = rows.iter().map( |r| ByteArray::from( r.0.as_str() ) ).collect();
+                   column.typed::<ByteArrayType>().write_batch( &v, None, None )?; },
+            1 => { let v : Vec<i32> = rows.iter().map( |r| r.1 ).collect();
+                   column.typed::<Int32Type>().write_batch( &v, None, None )?; },
+            2 => { let v : Vec<i64> = rows.iter().map( |r| r.2 as i64 ).collect();
+                   column.typed::<Int64Type>().write_batch( &v, None, None )?; },
+            3 => { let v : Vec<i64> = rows.iter().map( |r| r.3 as i64 ).collect();
+                   column.typed::<Int64Type>().write_batch( &v, None, None )?; },
+            _ => { let v : Vec<f64> = rows.iter().map( |r| r.4 ).collect();
+                   column.typed::<DoubleType>().write_batch( &v, None, None )?; },
+        }
+        column.close()?;
+        n += 1;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+// every run adds a new scan, so the database keeps the history of a tree
+#[cfg(feature = "sqlite")]
+const SQLITE_SCHEMA : &str = "
+    CREATE TABLE IF NOT EXISTS scans (
+        id        INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS entries (
+        id        INTEGER PRIMARY KEY,
+        scan_id   INTEGER NOT NULL REFERENCES scans(id),
+        parent_id INTEGER REFERENCES entries(id),
+        path      TEXT    NOT NULL,
+        depth     INTEGER NOT NULL,
+        bytes     INTEGER NOT NULL,
+        blocks    INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS entries_path ON entries(path);";
+
+#[cfg(feature = "sqlite")]
+impl<'a> Node<'a> {
+    fn insert_sqlite( self, stmt : &mut rusqlite::Statement, scan_id : i64,
+                      parent_id : Option<i64>, depth : i64 ) -> rusqlite::Result<()> {
+        let id = stmt.insert( rusqlite::params![ scan_id, parent_id, self.path().to_string_lossy(), depth,
+                                                 self.totals.size as i64, ( self.totals.usage / 512 ) as i64 ] )?;
+        if let Some(entries) = self.children() {
+            for entry in entries {
+                entry.insert_sqlite( stmt, scan_id, Some( id ), depth + 1 )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn write_sqlite( roots : &[Node], file : &Path ) -> rusqlite::Result<()> {
+    let timestamp = SystemTime::now().duration_since( UNIX_EPOCH ).map( |d| d.as_secs() ).unwrap_or( 0 );
+
+    let mut conn = rusqlite::Connection::open( file )?;
+    conn.execute_batch( SQLITE_SCHEMA )?;
+    let tx = conn.transaction()?;
+    tx.execute( "INSERT INTO scans (timestamp) VALUES (?1)", rusqlite::params![ timestamp as i64 ] )?;
+    let scan_id = tx.last_insert_rowid();
+    {
+        let mut stmt = tx.prepare( "INSERT INTO entries (scan_id, parent_id, path, depth, bytes, blocks) \
+                                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)" )?;
+        for root in roots {
+            root.insert_sqlite( &mut stmt, scan_id, None, 0 )?;
+        }
+    }
+    tx.commit()
+}
+
+// squarified treemap layout: areas sorted from largest to smallest, returns ( x, y, w, h )
+fn squarify( areas : &[f64], mut x : f64, mut y : f64, mut w : f64, mut h : f64 ) -> Vec<(f64, f64, f64, f64)> {
+    // worst aspect ratio of a row laid along a side of length 'side'
+    let worst = |row : &[f64], side : f64| {
+        let sum : f64 = row.iter().sum();
+        row.iter().map( |a| ( side * side * a / ( sum * sum ) ).max( sum * sum / ( side * side * a ) ) )
+                  .fold( 0.0, f64::max )
+    };
+
+    let mut rects = Vec::with_capacity( areas.len() );
+    let mut start = 0;
+    while start < areas.len() {
+        let side = w.min( h );
+        let mut end = start + 1;
+        while end < areas.len() && worst( &areas[start..end + 1], side ) <= worst( &areas[start..end], side ) {
+            end += 1;
+        }
+
+        // lay the row out along the shorter side and shrink the remaining space
+        let sum : f64 = areas[start..end].iter().sum();
+        if w >= h {
+            let rw = if h > 0.0 { sum / h } else { 0.0 };
+            let mut ry = y;
+            for a in &areas[start..end] {
+                let rh = if rw > 0.0 { a / rw } else { 0.0 };
+                rects.push( ( x, ry, rw, rh ) );
+                ry += rh;
+            }
+            x += rw;
+            w -= rw;
+        } else {
+            let rh = if w > 0.0 { sum / w } else { 0.0 };
+            let mut rx = x;
+            for a in &areas[start..end] {
+                let rw = if rh > 0.0 { a / rh } else { 0.0 };
+                rects.push( ( rx, y, rw, rh ) );
+                rx += rw;
+            }
+            y += rh;
+            h -= rh;
+        }
+        start = end;
+    }
+    rects
+}
+
+// translate the foreground color of an LS_COLORS entry to an SVG color
+fn svg_color( code : &str ) -> Option<String> {
+    let mut color = None;
+    let mut codes = code.split( ';' ).map( |n| n.parse::<usize>().unwrap_or( 0 ) );
+    while let Some(n) = codes.next() {
+        match n {
+            30..=37 => color = Some( xterm_color( n - 30 ) ),
+            90..=97 => color = Some( xterm_color( n - 90 + 8 ) ),
+            39      => color = None,
+            // extended colors take their arguments, also for the background
+            38 | 48 => {
+                let extended = match codes.next() {
+                    Some(5) => codes.next().map( |c| xterm_color( c.min( 255 ) ) ),
+                    Some(2) => {
+                        let rgb : Vec<usize> = codes.by_ref().take( 3 ).collect();
+                        if rgb.len() == 3 {
+                            Some( format!( "#{:02x}{:02x}{:02x}", rgb[0].min( 255 ), rgb[1].min( 255 ), rgb[2].min( 255 ) ) )
+                        } else { None }
+                    },
+                    _       => None,
+                };
+                if n == 38 && extended.is_some() {
+                    color = extended;
+                }
+            },
+            _       => {},
+        }
+    }
+    color
+}
+
+// cut a name to a width in columns, between graphemes, marking the cut with mark
+fn truncate_name( name : &str, width : usize, mark : char ) -> String {
+    if UnicodeWidthStr::width( name ) <= width {
+        return name.to_string();
+    }
+    let mut cut  = String::with_capacity( name.len() );
+    let mut used = 0;
+    for grapheme in name.graphemes( true ) {
+        let w = UnicodeWidthStr::width( grapheme );
+        if used + w + 1 > width {
+            break;
+        }
+        cut.push_str( grapheme );
+        used += w;
+    }
+    if width > 0 {
+        cut.push( mark );
+    }
+    cut
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname( buf.as_mut_ptr() as *mut libc::c_char, buf.len() ) } != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position( |&b| b == 0 ).unwrap_or( buf.len() );
+    String::from_utf8_lossy( &buf[..len] ).into_owned()
+}
+
+// an absolute file:// URL, with anything but unreserved chars and '/' percent encoded
+fn file_url( host : &str, path : &Path ) -> String {
+    let path = std::env::current_dir().map( |dir| dir.join( path ) ).unwrap_or( path.to_path_buf() );
+    let mut url = format!( "file://{}", host );
+    for &b in path.as_os_str().as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => url.push( b as char ),
+            _ => url.push_str( &format!( "%{:02X}", b ) ),
+        }
+    }
+    url
+}
+
+// the 16 basic colors, a 6x6x6 cube and 24 grays
+fn xterm_color( n : usize ) -> String {
+    const PALETTE : [&str; 16] = [
+        "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+        "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff" ];
+    const LEVELS : [usize; 6] = [ 0, 95, 135, 175, 215, 255 ];
+    match n {
+        0..=15   => PALETTE[n].to_string(),
+        16..=231 => {
+            let c = n - 16;
+            format!( "#{:02x}{:02x}{:02x}", LEVELS[c / 36], LEVELS[c / 6 % 6], LEVELS[c % 6] )
+        },
+        _        => {
+            let gray = 8 + 10 * ( n - 232 );
+            format!( "#{:02x}{:02x}{:02x}", gray, gray, gray )
+        },
+    }
+}
+
+fn xml_escape( text : &str ) -> String {
+    text.replace( '&', "&amp;" ).replace( '<', "&lt;" ).replace( '>', "&gt;" ).replace( '"', "&quot;" )
+}
+
+// by the key asked for, largest first when sizing or counting, and alphabetically like ls when naming
+fn sort_entries( entries : &mut [Entry], names : &Names, cfg : &Config ) {
+    entries.sort_by( |a, b| {
+        let dirs = if cfg.dirs_first { b.is_dir().cmp( &a.is_dir() ) } else { std::cmp::Ordering::Equal };
+        let ( name_a, name_b ) = ( names.show( a.name ), names.show( b.name ) );
+        let key = match cfg.sort {
+            SortKey::Size    => b.bytes.cmp( &a.bytes ),
+            SortKey::Name    => name_a.to_lowercase().cmp( &name_b.to_lowercase() ).then_with( || name_a.cmp( name_b ) ),
+            SortKey::Version => natural_cmp( name_a, name_b ),
+            SortKey::Count   => b.totals.count.cmp( &a.totals.count ).then_with( || b.bytes.cmp( &a.bytes ) ),
+        };
+        dirs.then( if cfg.reverse { key.reverse() } else { key } )
+    } );
+}
+
+// like ls -v, numbers inside names compare by their value, so log.2 goes before log.10
+fn natural_cmp( a : &str, b : &str ) -> std::cmp::Ordering {
+    let ( mut a, mut b ) = ( a.as_bytes(), b.as_bytes() );
+    while !a.is_empty() && !b.is_empty() {
+        let ord = if a[0].is_ascii_digit() && b[0].is_ascii_digit() {
+            let ( na, ra ) = split_number( a );
+            let ( nb, rb ) = split_number( b );
+            a = ra;
+            b = rb;
+            na.len().cmp( &nb.len() ).then( na.cmp( nb ) )
+        } else {
+            let ord = a[0].to_ascii_lowercase().cmp( &b[0].to_ascii_lowercase() );
+            a = &a[1..];
+            b = &b[1..];
+            ord
+        };
+        if ord != std::cmp::Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp( &b.len() )
+}
+
+// the digits at the start, without leading zeros, and what follows them
+fn split_number( text : &[u8] ) -> ( &[u8], &[u8] ) {
+    let end   = text.iter().position( |c| !c.is_ascii_digit() ).unwrap_or( text.len() );
+    let start = text[..end].iter().position( |&c| c != b'0' ).unwrap_or( end );
+    ( &text[start..end], &text[end..] )
+}
+
+// a parent's line, a branch, the last branch and what leads to the name
+fn tree_glyphs( style : TreeStyle ) -> ( char, char, char, char ) {
+    match style {
+        TreeStyle::Unicode => ( '│', '├', '└', '─' ),
+        TreeStyle::Rounded => ( '│', '├', '╰', '─' ),
+        TreeStyle::Double  => ( '║', '╠', '╚', '═' ),
+        TreeStyle::Heavy   => ( '┃', '┣', '┗', '━' ),
+        TreeStyle::Ascii   => ( '|', '|', '`', '-' ),
+    }
+}
+
+// one level of the tree, like "├─ " for a width of 3
+fn branch( first : char, fill : char, width : usize ) -> String {
+    let mut str = String::with_capacity( width * 3 );
+    str.push( first );
+    (2..width).for_each( |_| str.push( fill ) );
+    if width > 1 {
+        str.push( ' ' );
+    }
+    str
+}
+
+fn fmt_bar( bytes : &[u64], width : usize, style : BarStyle, color : Option<&str> ) -> String {
+    let width = width.saturating_sub( 2 ) as u64; // not including bars
+
+    // shades per level, and eighths of a cell to round off the entry's own bar
+    let ( block_char, partial ) : ( &[char], &[char] ) = match style {
+        BarStyle::Blocks  => ( &[ ' ', '░', '▒', '▓', '█' ], &[] ),
+        BarStyle::Braille => ( &[ ' ', '⠁', '⠉', '⠛', '⣿' ], &[ '⢀', '⢠', '⢰', '⢸', '⣸', '⣼', '⣾' ] ),
+        BarStyle::Fine    => ( &[ ' ', '░', '▒', '▓', '█' ], &[ '▁', '▂', '▃', '▄', '▅', '▆', '▇' ] ),
+        BarStyle::Ascii   => ( &[ ' ', '#' ], &[] ),
+    };
+
+    let mut bytesi = bytes.iter();
+    let mut total  = bytesi.next().unwrap();
+    let mut part   = bytesi.next().unwrap();
+    // filters can leave nothing to measure, which draws an empty bar
+    let mut bars   = if *total > 0 { ( part * width ) / total } else { 0 };
+    let mut pos    = width - bars;
+
+    let mut chr    = 0;
+    let mut level  = 1;
+    let levels = bytes.len() - 1;
+    let mut edge   = if levels == 1 && *total > 0 { ( pos, ( part * width ) % total * 8 / total ) } else { ( width, 0 ) };
+
+    let mut cells = Vec::with_capacity( width as usize );
+    for x in 0..width {
+        if x > pos {
+            total = part;
+            part  = bytesi.next().unwrap_or(&0);
+            let rest = ( part * bars ) % total;
+            bars  = ( part * bars ) / total;
+
+            pos = width - bars;
+            level += 1;
+            if level == levels {
+                edge = ( pos, rest * 8 / total );
+            }
+            chr += 1;
+            if chr == levels || chr >= block_char.len() {
+                chr = block_char.len() - 1;          // last level, solid '█'
+            }
+        }
+        cells.push( block_char[chr] );
+    }
+
+    let ( edge_pos, eighths ) = edge;
+    let mut start = edge_pos + 1;
+    if eighths > 0 && !partial.is_empty() && edge_pos < width {
+        cells[edge_pos as usize] = partial[eighths as usize - 1];
+        start = edge_pos;
+    }
+
+    // plain ASCII has its own borders too
+    let border = if let BarStyle::Ascii = style { '|' } else { '│' };
+    let mut str = String::with_capacity( width as usize + 2 );
+    str.push( border );
+    match color {
+        // only the entry's own part of the bar is colored
+        Some(color) if start < width => {
+            let ( head, tail ) = cells.split_at( start as usize );
+            str.extend( head );
+            str.push_str( &format!( "\x1b[{}m", color ) );
+            str.extend( tail );
+            str.push_str( "\x1b[0m" );
+        },
+        _ => str.extend( cells ),
+    }
+    str.push( border );
+    str
+}
+
+// green to yellow to red, as 24-bit or the closest of the 256 colors
+fn gradient_color( percent : u64, truecolor : bool ) -> String {
+    let pct = percent.min( 100 );
+    let ( r, g ) = if pct < 50 { ( 220 * pct / 50, 200 ) } else { ( 220, 200 * ( 100 - pct ) / 50 ) };
+    if truecolor {
+        format!( "38;2;{};{};0", r, g )
+    } else {
+        // each channel to the nearest of the 6 levels of the cube, its top level being the full one
+        format!( "38;5;{}", 16 + 36 * ( ( r * 5 + 110 ) / 220 ) + 6 * ( ( g * 5 + 100 ) / 200 ) )
+    }
+}
+
+// running is the size of the entry and the ones listed before it
+fn fmt_column( entry : &Entry, col : Column, parent_bytes : u64, running : u64, sizes : Sizes ) -> String {
+    match col {
+        Column::Size    => format!( "{:>13}", fmt_size_str( entry.bytes, sizes ) ),
+        Column::Percent => format!( "{:3}%", ( entry.bytes * 100 ).checked_div( parent_bytes ).unwrap_or( 0 ) ),
+        Column::Count   => format!( "{:>9}", entry.totals.count ),
+        Column::Mtime   => format!( "{:>16}", fmt_time( entry.totals.mtime ) ),
+        Column::Owner   => format!( "{:>17}", fmt_owner( entry ) ),
+        Column::Perms   => format!( "{:>10}", fmt_perms( entry ) ),
+        Column::Cumulative => format!( "{:3}%", ( running * 100 ).checked_div( parent_bytes ).unwrap_or( 0 ) ),
+        Column::Alloc   => format!( "{:>13} {:>13}", fmt_size_str( entry.totals.size, sizes ),
+                                    fmt_size_str( entry.totals.usage, sizes ) ),
+    }
+}
+
+// the names of the users and groups of a report, each looked up once since NSS may ask over the network
+#[derive(Default)]
+struct Owners {
+    users  : HashMap<u32, String>,
+    groups : HashMap<u32, String>,
+}
+
+static OWNERS : Mutex<Option<Owners>> = Mutex::new( None );
+
+// user:group, by name when known, or '-' for entries that aren't on disk
+fn fmt_owner( entry : &Entry ) -> String {
+    if entry.mode == 0 { return "-".to_string() }
+    let ( uid, gid ) = ( entry.uid, entry.gid );
+    let mut owners = OWNERS.lock().unwrap();
+    let owners = owners.get_or_insert_with( Owners::default );
+    let user = owners.users.entry( uid ).or_insert_with( || unsafe {
+        let pw = libc::getpwuid( uid );
+        if pw.is_null() { uid.to_string() } else { std::ffi::CStr::from_ptr( (*pw).pw_name ).to_string_lossy().into_owned() }
+    } );
+    let group = owners.groups.entry( gid ).or_insert_with( || unsafe {
+        let gr = libc::getgrgid( gid );
+        if gr.is_null() { gid.to_string() } else { std::ffi::CStr::from_ptr( (*gr).gr_name ).to_string_lossy().into_owned() }
+    } );
+    format!( "{}:{}", user, group )
+}
+
+// like ls -l, as in drwxr-xr-x
+fn fmt_perms( entry : &Entry ) -> String {
+    if entry.mode == 0 { return "-".to_string() }
+    let mode = entry.mode;
+    let mut perms = String::new();
+    perms.push( match file_kind( mode ) {
+        'f'  => '-',
+        kind => kind,
+    } );
+    for ( i, c ) in "rwxrwxrwx".chars().enumerate() {
+        perms.push( if mode & ( 0o400 >> i ) != 0 { c } else { '-' } );
+    }
+    perms
+}
+
+// local time as YYYY-MM-DD HH:MM
+fn fmt_time( secs : i64 ) -> String {
+    if secs == 0 { return "-".to_string() }
+    let t = secs as libc::time_t;
+    let mut tm : libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r( &t, &mut tm ) }.is_null() {
+        return "-".to_string();
+    }
+    format!( "{:04}-{:02}-{:02} {:02}:{:02}",
+             tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min )
+}
+
+// separators in the leading number of a formatted size, like 1,234.5 KiB
+fn group_digits( size : &str, sep : char, point : char ) -> String {
+    let digits = size.find( |c : char| !c.is_ascii_digit() ).unwrap_or( size.len() );
+    let mut out = String::new();
+    for ( i, c ) in size[..digits].chars().enumerate() {
+        if i > 0 && ( digits - i ) % 3 == 0 {
+            out.push( sep );
+        }
+        out.push( c );
+    }
+    let rest = &size[digits..];
+    if let Some(frac) = rest.strip_prefix( '.' ) {
+        out.push( point );
+        out.push_str( frac );
+    } else {
+        out.push_str( rest );
+    }
+    out
+}
+
+// thousands separator and decimal point of LC_NUMERIC, ',' and '.' if it has none
+fn locale_separators() -> ( char, char ) {
+    unsafe {
+        libc::setlocale( libc::LC_NUMERIC, b"\0".as_ptr() as *const libc::c_char );
+        let lc = libc::localeconv();
+        if lc.is_null() { return ( ',', '.' ) }
+        let first = |s : *mut libc::c_char, def| if s.is_null() { def } else {
+            std::ffi::CStr::from_ptr( s ).to_string_lossy().chars().next().unwrap_or( def )
+        };
+        ( first( (*lc).thousands_sep, ',' ), first( (*lc).decimal_point, '.' ) )
+    }
+}
+
+// N chars or N% of the line
+fn parse_width( val : &str ) -> Option<ColWidth> {
+    if let Some(pct) = val.strip_suffix( '%' ) {
+        pct.parse().ok().filter( |p| *p <= 100 ).map( ColWidth::Percent )
+    } else {
+        val.parse().ok().map( ColWidth::Chars )
+    }
+}
+
+// N with an optional B/K/M/G/T unit, in powers of 1024
+fn parse_size( val : &str ) -> Option<u64> {
+    if !Regex::new(r"^\d+\D?$").unwrap().is_match( val ){
+        return None;
+    }
+
+    let unit = val.matches(char::is_alphabetic).next().unwrap_or("B");
+    let num : Vec<&str> = val.matches(char::is_numeric).collect();
+    let num : u64       = num.concat().parse().ok()?;
+
+    let factor = match unit {
+        "b" | "B" => 1024u64.pow(0),
+        "k" | "K" => 1024u64.pow(1),
+        "m" | "M" => 1024u64.pow(2),
+        "g" | "G" => 1024u64.pow(3),
+        "t" | "T" => 1024u64.pow(4),
+        _         => 1024u64.pow(0),
+    };
+    num.checked_mul( factor )
+}
+
+fn fmt_size_str( bytes : u64, sizes : Sizes ) -> String {
+    let size = fmt_size_units( bytes, sizes.units, sizes.precision );
+    match sizes.group {
+        Some(( sep, point )) => group_digits( &size, sep, point ),
+        None                 => size,
+    }
+}
+
+fn fmt_size_units( bytes : u64, units : Units, precision : usize ) -> String {
+    let ( k, names ) = match units {
+        Units::Binary => ( 1024, [ "KiB", "MiB", "GiB", "TiB" ] ),
+        Units::Si     => ( 1000, [ "KB" , "MB" , "GB" , "TB"  ] ),
+        Units::Bytes  => return format!( "{} B", bytes ),
+        Units::Block( block ) => return bytes.div_ceil( block ).to_string(),
+    };
+    let b = bytes as f64;
+    let p = precision;
+    if      bytes < k              { format!( "{} B"     , bytes                                  ) }
+    else if bytes < k.pow(2)       { format!( "{:.*} {}" , p, b/(k as f64)            , names[0] ) }
+    else if bytes < k.pow(3)       { format!( "{:.*} {}" , p, b/(k.pow(2) as f64)     , names[1] ) }
+    else if bytes < k.pow(4)       { format!( "{:.*} {}" , p, b/(k.pow(3) as f64)     , names[2] ) }
+    else                           { format!( "{:.*} {}" , p, b/(k.pow(4) as f64)     , names[3] ) }
+}
+
+// stat is the one of path, stat'ed once for all that is needed of it
+fn stat_totals( path: &Path, rel : &Path, ignores : &[Ignore], cfg : &Config, stat : io::Result<Stat> ) -> Totals {
+    match stat {
+        Ok(stat) if stat.is_dir => {
+            // an unchanged directory is listed as the cache has it, with the stats of its files
+            let cached = if caching( cfg ) { cached_dir( path, Some( &stat ), cfg ) } else { None };
+            dir_totals( path, rel, ignores, cfg, stat, cached.map( |( _, list )| list ) )
+        },
+        stat => totals_from_stat( path, rel, stat, cfg ),
+    }
+}
+
+fn dir_totals( path: &Path, rel : &Path, ignores : &[Ignore], cfg : &Config,
+               stat : Stat, cached : Option<Listing> ) -> Totals {
+    let mut totals = own_totals( path, rel, Ok( stat ), cfg );
+    let own = totals;
+
+    let fresh  = cached.is_none();
+    let listed = cached.or_else( || try_read_dir( path, rel, &stat, cfg.follow_flag ) );
+
+    if let Some(mut dir_list) = listed {
+        let ignores = read_ignores( path, rel, ignores, cfg );
+        let mut files = Vec::new();
+        let mut slots = Vec::new();
+        let mut found = Vec::new();
+        let stride = if cfg.estimate && dir_list.len() > SAMPLE_SIZE { dir_list.len() / SAMPLE_SIZE } else { 1 };
+        for ( slot, &( ref path, dir, stat ) ) in dir_list.iter().enumerate().step_by( stride ) {
+            if INTERRUPTED.load( Ordering::Relaxed ) { break }
+            let rel = rel.join( path.file_name().unwrap_or_default() );
+            let ( is_dir, stat ) = match stat {
+                Some(stat) => ( stat.is_dir, Some( Ok( stat ) ) ),
+                None       => listed_type( path, dir, cfg ),
+            };
+            if skipped( cfg, path, &rel, &ignores, is_dir ) { continue }
+
+            // what is known not to be a directory is stat'ed all at once
+            if stat.is_none() && !is_dir {
+                files.push( path.clone() );
+                slots.push( ( slot, rel ) );
+                continue;
+            }
+            let stat = stat.unwrap_or_else( || try_stat_fields( path, cfg.follow_flag ) );
+            if skipped_by_stat( cfg, path, &rel, &stat ) { continue }
+            found.push( stat_totals( path, &rel, &ignores, cfg, stat ) );
+        }
+        for ( ( path, ( slot, rel ) ), stat ) in files.iter().zip( slots ).zip( stat_all( &files, cfg.follow_flag ) ) {
+            if let Ok(ref stat) = stat {
+                dir_list[slot].2 = Some( *stat );
+            }
+            if skipped_by_stat( cfg, path, &rel, &stat ) { continue }
+            found.push( totals_from_stat( path, &rel, stat, cfg ) );
+        }
+        totals.add( &extrapolate( &found, dir_list.len(), stride, cfg ) );
+        if fresh && caching( cfg ) && !INTERRUPTED.load( Ordering::Relaxed ) {
+            cache_dir( path, &stat, dir_list );
+        }
+        // other links may lead here too
+        if cfg.follow_flag {
+            claim( rel, ( stat.dev, stat.ino ), totals, own );
+        }
+    }
+    totals
+}
+
+// the totals of every stride-th entry of a directory of len entries, scaled up to all of them
+fn extrapolate( found : &[Totals], len : usize, stride : usize, cfg : &Config ) -> Totals {
+    let mut sum = Totals::default();
+    for totals in found {
+        sum.add( totals );
+    }
+    if stride == 1 { return sum }
+
+    // the variance of n times the mean of the sample, where skipped entries count as nothing
+    let n = len as f64;
+    let m = len.div_ceil( stride ) as f64;
+    let mean    = measure( &sum, cfg ) as f64 / m;
+    let squares = found.iter().map( |t| ( measure( t, cfg ) as f64 ).powi( 2 ) ).sum::<f64>();
+    let var     = ( squares - m * mean * mean ).max( 0.0 ) / ( m - 1.0 );
+    *VARIANCE.lock().unwrap() += n * n * ( 1.0 - m / n ) * var / m;
+    SAMPLED.store( true, Ordering::Relaxed );
+
+    let scale = |x : u64| ( x as f64 * n / m ).round() as u64;
+    Totals { size : scale( sum.size ), usage : scale( sum.usage ), count : scale( sum.count ), dirs : scale( sum.dirs ), mtime : sum.mtime }
+}
+
+// with --estimate, how far off the total of bytes may be, in percent at 95% confidence
+fn estimate_margin( bytes : u64 ) -> Option<f64> {
+    if !SAMPLED.load( Ordering::Relaxed ) || bytes == 0 { return None }
+    Some( 1.96 * VARIANCE.lock().unwrap().sqrt() * 100.0 / bytes as f64 )
+}
+
+// whether a listed path is a directory, stat'ed only when the listing doesn't tell, like for links
+fn listed_type( path : &Path, dir : Option<bool>, cfg : &Config ) -> ( bool, Option<io::Result<Stat>> ) {
+    match dir {
+        Some(dir) => ( dir, None ),
+        None      => {
+            let stat = try_stat_fields( path, cfg.follow_flag );
+            ( stat.as_ref().is_ok_and( |stat| stat.is_dir ), Some( stat ) )
+        },
+    }
+}
+
+// argument filters, applied while walking so skipped subtrees are never read
+// these only need the name and type, so what they leave out is never stat'ed
+fn skipped( cfg : &Config, path : &Path, rel : &Path, ignores : &[Ignore], is_dir : bool ) -> bool {
+    let name = rel.file_name().map_or( &b""[..], OsStr::as_bytes );
+    let skip = cfg.exclude.iter().any( |p| glob_matches( p, rel, cfg.icase_flag ) ) ||
+               cfg.exclude_path.iter().any( |p| excludes_path( p, path, rel, cfg.icase_flag ) ) ||
+               !cfg.count_hidden && hides( cfg, name, is_dir )      ||
+               cfg.no_dir_flg && is_dir                            ||
+               cfg.gitignore_flag && ( name == b".git" || ignored( ignores, rel, is_dir ) ) ||
+               cfg.caches_flag && is_dir && is_cache_dir( path );
+    if skip {
+        EXCLUDED.fetch_add( 1, Ordering::Relaxed );
+    }
+    skip
+}
+
+// the filters that look at the stat of what the others let through
+fn skipped_by_stat( cfg : &Config, path : &Path, rel : &Path, stat : &io::Result<Stat> ) -> bool {
+    let skip = match *stat {
+        Ok(ref stat) if stat.is_dir => cfg.pseudo_fs.contains( &( stat.dev, stat.ino ) ),
+        // links to directories go with them, only what --files-only skips is stat'ed through
+        Ok(ref stat) if stat.link && cfg.no_dir_flg && path.is_dir() => true,
+        _                           => !counted( cfg, path, rel, stat ),
+    };
+    if skip {
+        EXCLUDED.fetch_add( 1, Ordering::Relaxed );
+    }
+    skip
+}
+
+// numeric ids are taken as they are, names are looked up in the passwd or group database
+fn lookup_id( name : &str, group : bool ) -> Option<u32> {
+    if let Ok(id) = name.parse() { return Some( id ) }
+    let cname = std::ffi::CString::new( name ).ok()?;
+    unsafe {
+        if group {
+            let gr = libc::getgrnam( cname.as_ptr() );
+            if gr.is_null() { None } else { Some( (*gr).gr_gid ) }
+        } else {
+            let pw = libc::getpwnam( cname.as_ptr() );
+            if pw.is_null() { None } else { Some( (*pw).pw_uid ) }
+        }
+    }
+}
+
+// ( directory, filesystem type ) of what /proc/mounts lists, in mount order
+fn mounts() -> Vec<(String, String)> {
+    let mounts = fs::read_to_string( "/proc/mounts" ).unwrap_or_default();
+    let mut list = Vec::new();
+    for line in mounts.lines() {
+        let mut fields = line.split( ' ' ).skip( 1 );
+        let ( dir, fstype ) = match ( fields.next(), fields.next() ) {
+            ( Some(dir), Some(fstype) ) => ( dir, fstype ),
+            _                           => continue,
+        };
+
+        // spaces and such are octal escaped, like \040
+        let dir = dir.replace( "\\040", " " ).replace( "\\011", "\t" ).replace( "\\012", "\n" ).replace( "\\134", "\\" );
+        list.push( ( dir, fstype.to_string() ) );
+    }
+    list
+}
+
+// device and inode of the virtual filesystems mounted, which hold no disk data
+fn pseudo_mounts() -> Vec<(u64, u64)> {
+    const PSEUDO : &[&str] = &[ "proc", "sysfs", "devtmpfs", "devpts", "tmpfs", "ramfs", "cgroup", "cgroup2",
+                                "securityfs", "debugfs", "tracefs", "configfs", "pstore", "bpf", "mqueue",
+                                "hugetlbfs", "fusectl", "binfmt_misc", "efivarfs", "selinuxfs", "autofs" ];
+    let mut list = Vec::new();
+    for ( dir, fstype ) in mounts() {
+        if !PSEUDO.contains( &fstype.as_str() ) { continue }
+        if let Ok(meta) = fs::symlink_metadata( &dir ) {
+            #[cfg(target_os = "linux")]
+            list.push( ( meta.st_dev(), meta.st_ino() ) );
+            #[cfg(target_os = "macos")]
+            list.push( ( meta.dev() as u64, meta.ino() ) );
+        }
+    }
+    list
+}
+
+// https://bford.info/cachedir/
+fn is_cache_dir( path : &Path ) -> bool {
+    const SIGNATURE : &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+    let mut head = [0; 43];
+    match fs::File::open( path.join( "CACHEDIR.TAG" ) ) {
+        Ok(mut file) => file.read_exact( &mut head ).is_ok() && head == SIGNATURE,
+        Err(_)       => false,
+    }
+}
+
+// a .gitignore line, relative to the directory holding it
+#[derive(Clone)]
+struct Ignore {
+    base     : PathBuf,
+    pattern  : String,
+    negate   : bool,
+    dir_only : bool,
+    anchored : bool,
+}
+
+// the rules in effect below path: those from above plus its own ignore files
+fn read_ignores<'a>( path : &Path, rel : &Path, ignores : &'a [Ignore], cfg : &Config ) -> Cow<'a, [Ignore]> {
+    if !cfg.gitignore_flag { return Cow::Borrowed( ignores ) }
+
+    let mut rules = Vec::new();
+    // .gitignore comes last so it has the final say
+    for file in &[ ".git/info/exclude", ".gitignore" ] {
+        let text = match fs::read_to_string( path.join( file ) ) {
+            Ok(text) => text,
+            Err(_)   => continue,
+        };
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with( '#' ) { continue }
+
+            let negate   = line.starts_with( '!' );
+            let line     = if negate { &line[1..] } else { line };
+            let line     = line.strip_prefix( '\\' ).unwrap_or( line );
+            let dir_only = line.ends_with( '/' );
+            let line     = line.trim_end_matches( '/' );
+            let anchored = line.contains( '/' );
+            rules.push( Ignore {
+                base    : rel.to_path_buf(),
+                pattern : line.trim_start_matches( '/' ).to_string(),
+                negate,
+                dir_only,
+                anchored,
+            } );
+        }
+    }
+    if rules.is_empty() {
+        Cow::Borrowed( ignores )
+    } else {
+        let mut all = ignores.to_vec();
+        all.extend( rules );
+        Cow::Owned( all )
+    }
+}
+
+// the last matching rule wins, as in git
+fn ignored( ignores : &[Ignore], rel : &Path, is_dir : bool ) -> bool {
+    let mut ignored = false;
+    for rule in ignores {
+        if rule.dir_only && !is_dir { continue }
+        let sub = match rel.strip_prefix( &rule.base ) {
+            Ok(sub) => sub,
+            Err(_)  => continue,
+        };
+        let hit = if rule.anchored {
+            glob_match( rule.pattern.as_bytes(), sub.as_os_str().as_bytes() )
+        } else {
+            sub.file_name().is_some_and( |name| glob_match( rule.pattern.as_bytes(), name.as_bytes() ) )
+        };
+        if hit {
+            ignored = !rule.negate;
+        }
+    }
+    ignored
+}
+
+fn hides( cfg : &Config, name : &[u8], is_dir : bool ) -> bool {
+    name.first() == Some( &b'.' ) && match cfg.hidden {
+        Hidden::Show  => false,
+        Hidden::Hide  => true,
+        Hidden::Dirs  => is_dir,
+        Hidden::Files => !is_dir,
+    }
+}
+
+// the file filters leave out what doesn't match, but directories are still walked
+fn file_filters( cfg : &Config ) -> bool {
+    !cfg.include.is_empty() || cfg.uid.is_some() || cfg.gid.is_some() || !cfg.types.is_empty() ||
+    !cfg.exts.is_empty() || !cfg.not_exts.is_empty()
+}
+
+fn counted( cfg : &Config, path : &Path, rel : &Path, stat : &io::Result<Stat> ) -> bool {
+    if !file_filters( cfg ) { return true }
+    let stat = match *stat {
+        Ok(ref stat) => stat,
+        Err(_)       => return false,
+    };
+    let kind = file_kind( stat.mode );
+
+    included( cfg, rel ) && cfg.uid.is_none_or( |u| u == stat.uid ) && cfg.gid.is_none_or( |g| g == stat.gid ) &&
+    ( cfg.types.is_empty() || cfg.types.contains( &kind ) ) &&
+    ( cfg.exts.is_empty() && cfg.not_exts.is_empty() || {
+        let ext = file_ext( path );
+        ( cfg.exts.is_empty() || cfg.exts.contains( &ext ) ) && !cfg.not_exts.contains( &ext )
+    } )
+}
+
+// lowercase and without the dot, empty for files without one
+fn file_ext( path : &Path ) -> String {
+    path.extension().map_or( String::new(), |e| e.to_string_lossy().to_lowercase() )
+}
+
+// the letters of find -type, from the S_IFMT bits of a mode, the same on Linux and macOS
+fn file_kind( mode : u32 ) -> char {
+    match mode & 0o170000 {
+        0o040000 => 'd',
+        0o120000 => 'l',
+        0o060000 => 'b',
+        0o020000 => 'c',
+        0o140000 => 's',
+        0o010000 => 'p',
+        _        => 'f',
+    }
+}
+
+// a directory that doesn't pass the file filters only adds what it holds
+fn own_totals( path : &Path, rel : &Path, stat : io::Result<Stat>, cfg : &Config ) -> Totals {
+    if counted( cfg, path, rel, &stat ) {
+        totals_from_stat( path, rel, stat, cfg )
+    } else {
+        Totals::default()
+    }
+}
+
+// everything below a directory that matches is included too
+fn included( cfg : &Config, rel : &Path ) -> bool {
+    cfg.include.is_empty() ||
+    rel.ancestors().filter( |a| !a.as_os_str().is_empty() )
+                   .any( |a| cfg.include.iter().any( |p| glob_matches( p, a, cfg.icase_flag ) ) )
+}
+
+// a whole path, either below the scanned directory or as it was reached from the arguments
+fn excludes_path( pattern : &str, path : &Path, rel : &Path, icase : bool ) -> bool {
+    let path = path.strip_prefix( "." ).unwrap_or( path );
+    glob_text( pattern, rel.as_os_str(), icase ) || glob_text( pattern, path.as_os_str(), icase )
+}
+
+fn glob_matches( pattern : &str, rel : &Path, icase : bool ) -> bool {
+    if pattern.contains( '/' ) {
+        glob_text( pattern, rel.as_os_str(), icase )
+    } else {
+        rel.file_name().is_some_and( |name| glob_text( pattern, name, icase ) )
+    }
+}
+
+// patterns are already lowercase when ignoring case
+fn glob_text( pattern : &str, text : &OsStr, icase : bool ) -> bool {
+    if icase {
+        glob_match( pattern.as_bytes(), text.to_string_lossy().to_lowercase().as_bytes() )
+    } else {
+        glob_match( pattern.as_bytes(), text.as_bytes() )
+    }
+}
+
+// shell style globs: '*' and '?' stop at '/', '**' crosses directories, '[a-z]' and '[!a-z]' classes
+fn glob_match( pat : &[u8], text : &[u8] ) -> bool {
+    match pat.first() {
+        None        => text.is_empty(),
+        Some(b'*') if pat.get( 1 ) == Some( &b'*' ) => {
+            let rest = &pat[2..];
+            // 'a/**/b' also matches 'a/b'
+            if rest.first() == Some( &b'/' ) && glob_match( &rest[1..], text ) { return true }
+            ( 0..=text.len() ).any( |i| glob_match( rest, &text[i..] ) )
+        },
+        Some(b'*')  => {
+            ( 0..=text.len() ).take_while( |&i| i == 0 || text[i-1] != b'/' )
+                              .any( |i| glob_match( &pat[1..], &text[i..] ) )
+        },
+        Some(b'?')  => {
+            text.first().is_some_and( |&c| c != b'/' ) && glob_match( &pat[1..], &text[1..] )
+        },
+        Some(b'[')  => match text.first() {
+            Some(&c) if c != b'/' => match glob_class( pat, c ) {
+                Some(( found, len )) => found && glob_match( &pat[len..], &text[1..] ),
+                None                 => c == b'[' && glob_match( &pat[1..], &text[1..] ),
+            },
+            _                     => false,
+        },
+        Some(&p)    => text.first() == Some( &p ) && glob_match( &pat[1..], &text[1..] ),
+    }
+}
+
+// whether c is in the class at the start of pat, and the length of the class
+fn glob_class( pat : &[u8], c : u8 ) -> Option<( bool, usize )> {
+    let negate = pat.get( 1 ) == Some( &b'!' ) || pat.get( 1 ) == Some( &b'^' );
+    let start  = if negate { 2 } else { 1 };
+    let mut i  = start;
+    let mut found = false;
+    while i < pat.len() && ( pat[i] != b']' || i == start ) {
+        if i + 2 < pat.len() && pat[i+1] == b'-' && pat[i+2] != b']' {
+            found |= pat[i] <= c && c <= pat[i+2];
+            i += 3;
+        } else {
+            found |= pat[i] == c;
+            i += 1;
+        }
+    }
+    if i == pat.len() { return None } // unterminated, '[' is literal
+    Some(( found != negate, i + 1 ))
+}
+
+fn color_from_path<'a>( path : &Path, color_dict : &'a Dict<String> ) -> Option<&'a str> {
+    color_from_stat( path, try_stat_fields( path, false ).ok().as_ref(), color_dict )
+}
+
+// links are colored as links, also when stat is of where they lead
+fn color_from_stat<'a>( path : &Path, stat : Option<&Stat>, color_dict : &'a Dict<String> ) -> Option<&'a str> {
+    let kind = stat.map_or( 0, |stat| stat.mode & libc::S_IFMT );
+    if stat.is_some_and( |stat| stat.link ) {
+        // exists follows the link from where it is, a link gone since the stat is an orphan too
+        if path.exists() {
+            if let Some( col ) = color_dict.get( "ln" ) {
+                return Some( col );
+            }
+        } else {
+            if let Some( col ) = color_dict.get( "or" )  {
+                return Some( col );
+            }
+        }
+    }
+    if let Some(stat) = stat {
+        let mode = stat.mode;
+        if stat.is_dir {
+            if mode & 0o002 != 0 {  // dir other writable
+                if let Some( col ) = color_dict.get( "ow" ) {
+                    return Some( col );
+                }
+            }
+            if let Some( col ) = color_dict.get( "di" ) {
+                return Some( col );
+            }
+        }
+        if mode & 0o111 != 0 {  // executable
+            if let Some( col ) = color_dict.get( "ex" ) {
+                return Some( col );
+            }
+        }
+    }
+    if let Some( col ) = color_from_ext( path, color_dict ) {
+        return Some( col );
+    }
+    if kind == libc::S_IFREG {
+        if let Some( col ) = color_dict.get( "fi" ) {
+            return Some( col );
+        }
+        else { return None }
+    }
+    // we are assuming it can only be a 'bd','cd'. can also be 'pi','so' or 'no'
+    if let Some( col ) = color_dict.get( "bd" ) {
+        return Some( col );
+    }
+    None
+}
+
+fn color_from_ext<'a>( path : &Path, color_dict : &'a Dict<String> ) -> Option<&'a str> {
+    if let Some( ext_str ) = path.extension() {
+        for col in color_dict {
+            if !col.key.starts_with( "*." ) { continue }
+            let key = col.key.trim_start_matches( "*." );
+            if ext_str == key {
+                return Some( &col.val );
+            }
+        }
+    }
+    None
+}
+
+fn terminal_width() -> u16 {
+    if let Some( ( Width(w), Height(_h) ) ) = terminal_size() {
+        w
+    } else {
+        eprintln!("Unable to get terminal size");
+        DEF_WIDTH
+    }
+}
+
+fn terminal_height() -> Option<u16> {
+    terminal_size().map( |( Width(_w), Height(h) )| h )
+}
+
+fn print_usage( program: &str, opts: &Options ) {
+    let brief = format!( "Usage: {} [options] <path> [<path>..]", program );
+    print!( "{}", opts.usage( &brief ) );
+}
+
+// built-in colors, in the LS_COLORS format
+fn theme_colors( theme : &str ) -> Option<&'static str> {
+    match theme {
+        "dark"      => Some( "di=01;34:ln=01;36:or=01;31:ex=01;32:ow=34;42:bd=01;33:\
+                            *.tar=01;31:*.gz=01;31:*.zip=01;31:*.xz=01;31:*.zst=01;31:\
+                            *.jpg=01;35:*.png=01;35:*.mp3=00;36:*.mp4=01;35:*.mkv=01;35" ),
+        "light"     => Some( "di=34:ln=36:or=31:ex=32:ow=34;47:bd=33:\
+                            *.tar=31:*.gz=31:*.zip=31:*.xz=31:*.zst=31:\
+                            *.jpg=35:*.png=35:*.mp3=36:*.mp4=35:*.mkv=35" ),
+        "solarized" => Some( "di=38;5;33:ln=38;5;37:or=38;5;160:ex=38;5;64:ow=38;5;33;48;5;235:bd=38;5;136:\
+                            *.tar=38;5;166:*.gz=38;5;166:*.zip=38;5;166:*.xz=38;5;166:*.zst=38;5;166:\
+                            *.jpg=38;5;125:*.png=38;5;125:*.mp3=38;5;37:*.mp4=38;5;125:*.mkv=38;5;125" ),
+        "mono"      => Some( "di=01:ln=03:or=07:ex=04" ),
+        _           => None,
+    }
+}
+
+fn create_color_dict( ls_colors : &str ) -> Dict<String> {
+    let mut color_dict = Dict::<String>::new();
+    for entry in ls_colors.split(':') {
+        let     line = entry.replace("\"","");
+        let mut line = line.splitn( 2, '=' );
+        let key      = line.next().unwrap();
+        let val      = match line.next() {
+            Some(val) if !key.is_empty() && !val.is_empty() => val,
+            _                                               => continue,
+        };
+
+        color_dict.add( key.to_string(), val.to_string() );
+    }
+    color_dict
+}
+
+// levels to keep below path, counting itself
+fn depth_for( cfg : &Config, path : &Path ) -> Option<u8> {
+    if let Some(&( _, depth )) = cfg.path_depths.iter().rev().find( |( p, _ )| p == path ) {
+        Some( depth.saturating_add( 1 ) )
+    } else if cfg.depth_flag {
+        Some( cfg.depth.saturating_add( 1 ) )
+    } else {
+        None
+    }
+}
+
+pub fn run( cfg: &Config ) {
+    // open the report file before scanning, so a bad path fails early
+    let stdout = io::stdout();
+    let mut out : Box<dyn Write> = match cfg.output {
+        Some(ref file) => match fs::File::create( file ) {
+            Ok(f)    => Box::new( io::BufWriter::new( f ) ),
+            Err(err) => {
+                eprintln!( "Couldn't write {} ({:?})", file.display(), err.kind() );
+                return;
+            },
+        },
+        None => Box::new( stdout.lock() ),
+    };
+
+    // what a run found starts from nothing, also when it isn't the first one of the process
+    for counter in &[ &ERRORS, &EXCLUDED, &AGGREGATED ] {
+        counter.store( 0, Ordering::Relaxed );
+    }
+    SCANNED.store( 0, Ordering::Relaxed );
+    FOUND.store( 0, Ordering::Relaxed );
+    SAMPLED.store( false, Ordering::Relaxed );
+    INTERRUPTED.store( false, Ordering::Relaxed );
+    *VARIANCE.lock().unwrap() = 0.0;
+    *VISITED.lock().unwrap() = None;
+    TIMINGS.lock().unwrap().clear();
+
+    WORKERS.store( cfg.jobs - 1, Ordering::SeqCst );
+    TIMING.store( cfg.timings, Ordering::Relaxed );
+    if cfg.timings {
+        TIMINGS.lock().unwrap().push( ( "<rest>".to_string(), Timings::default() ) );
+    }
+    let start = Instant::now();
+
+    let mut tree = if let Some(ref file) = cfg.load {
+        match read_snapshot( file, cfg ) {
+            Ok(tree) => tree,
+            Err(err)  => {
+                eprintln!( "Couldn't read {} ({:?})", file.display(), err.kind() );
+                return;
+            },
+        }
+    } else if let Some(ref file) = cfg.import_du {
+        match read_du( file, cfg ) {
+            Ok(tree) => tree,
+            Err(err)  => {
+                eprintln!( "Couldn't read {} ({:?})", file.display(), err.kind() );
+                return;
+            },
+        }
+    } else {
+        *STARTED.lock().unwrap() = Some( Instant::now() );
+        unsafe {
+            libc::signal( libc::SIGINT,  on_interrupt as extern "C" fn( libc::c_int ) as libc::sighandler_t );
+            libc::signal( libc::SIGUSR1, on_status    as extern "C" fn( libc::c_int ) as libc::sighandler_t );
+        }
+        if cfg.cache != CacheMode::Off {
+            load_cache( cfg );
+        }
+        let progress = if cfg.progress { Some( Progress::start( cfg.sizes, expected_entries( cfg ) ) ) } else { None };
+
+        // snapshots keep the whole tree, so they can be rendered at any depth later
+        let scan = |i : usize, path : &Path| {
+            ROOT.with( |r| r.set( i as u32 ) );
+            let whole = cfg.save.is_some() || whole_scan( cfg ) || cfg.fit_flag;
+            let scan_depth = if whole { None }
+                             else if let Format::Summarize = cfg.format { Some( 1 ) }
+                             else { depth_for( cfg, path ) };
+            let mut tree = Tree::default();
+            let entry = tree.scan( path, Path::new( "" ), &[], cfg, scan_depth, try_stat_fields( path, cfg.follow_flag ) );
+            ( tree, entry )
+        };
+
+        // paths on different disks don't wait for each other, the threads left go to their directories
+        let scan = &scan;
+        let mut scans : Vec<( Tree, Entry )> = thread::scope( |scope| {
+            let scans : Vec<_> = cfg.paths.iter().enumerate().map( |( i, path )| {
+                if i + 1 < cfg.paths.len() && take_worker() {
+                    Err( scope.spawn( move || {
+                        let scan = scan( i, path );
+                        WORKERS.fetch_add( 1, Ordering::SeqCst );
+                        scan
+                    } ) )
+                } else {
+                    Ok( scan( i, path ) )
+                }
+            } ).collect();
+            scans.into_iter().map( |scan| match scan {
+                Ok(scan)    => scan,
+                Err(thread) => thread.join().unwrap(),
+            } ).collect()
+        } );
+
+        // the other paths join the tree of the first one
+        let ( mut tree, first ) = scans.remove( 0 );
+        let mut roots = vec![ first ];
+        for ( other, entry ) in scans {
+            roots.push( tree.graft( other, entry ) );
+        }
+        tree.plant( roots, cfg );
+        tree.settle( cfg );
+        if let Some(progress) = progress {
+            progress.stop();
+        }
+        unsafe { libc::signal( libc::SIGINT, libc::SIG_DFL ) };
+        if let Err(err) = save_cache( cfg ) {
+            eprintln!( "Couldn't write the scan cache ({:?})", err.kind() );
+        }
+        tree
+    };
+
+    let scan_time = start.elapsed();
+
+    // an interrupted scan would take the place of a whole one, with nothing telling them apart
+    if let Some(ref file) = cfg.save {
+        if INTERRUPTED.load( Ordering::Relaxed ) {
+            eprintln!( "Interrupted, {} was not written", file.display() );
+        } else if let Err(err) = write_snapshot( &roots( tree.top() ), file ) {
+            eprintln!( "Couldn't write {} ({:?})", file.display(), err.kind() );
+        }
+    }
+
+    // reports over every file are made before the tree is cut and aggregated
+    let start = Instant::now();
+    let mut scan_report = Vec::new();
+    if whole_scan( cfg ) {
+        print_scan( &roots( tree.top() ), cfg, &mut scan_report ).ok(); // writing to memory can't fail
+    }
+    let mut render_time = start.elapsed();
+
+    // with --fit, try deeper levels until the report would scroll
+    let start = Instant::now();
+    let depth = if cfg.fit_flag { Some( fit_depth( &tree, cfg ) ) } else { None };
+    assemble( &mut tree, cfg, depth );
+    let sort_time = start.elapsed();
+
+    // the root takes a line too, as a header or a line of its own, and the footer takes two
+    if let Some(lines) = cfg.max_lines {
+        let mut list = Vec::new();
+        tree.top().flatten( &mut list );
+        let mut sizes : Vec<u64> = list[1..].iter().map( |e| e.bytes ).collect();
+        let room = lines.saturating_sub( 1 + if cfg.footer_flag { 2 } else { 0 } ) / entry_lines( cfg );
+        if sizes.len() > room {
+            sizes.sort_unstable_by( |a, b| b.cmp( a ) );
+            let min  = if room > 0 { sizes[room - 1] } else { u64::MAX };
+            let mut ties = sizes[..room].iter().filter( |&&b| b == min ).count();
+            let top = tree.top;
+            tree.keep_largest( top, min, &mut ties );
+        }
+    }
+
+    let entry = tree.top();
+    if let Some(ref file) = cfg.treemap {
+        if let Err(err) = write_treemap( entry, file ) {
+            eprintln!( "Couldn't write {} ({:?})", file.display(), err.kind() );
+        }
+    }
+    if let Some(ref file) = cfg.prometheus {
+        if let Err(err) = write_prometheus( &roots( entry ), file ) {
+            eprintln!( "Couldn't write {} ({:?})", file.display(), err.kind() );
+        }
+    }
+    #[cfg(feature = "parquet")]
+    {
+        if let Some(ref file) = cfg.parquet {
+            if let Err(err) = write_parquet( &roots( entry ), file ) {
+                eprintln!( "Couldn't write {} ({})", file.display(), err );
+            }
+        }
+    }
+    #[cfg(feature = "sqlite")]
+    {
+        if let Some(ref file) = cfg.sqlite {
+            if let Err(err) = write_sqlite( &roots( entry ), file ) {
+                eprintln!( "Couldn't write {} ({})", file.display(), err );
+            }
+        }
+    }
+
+    let start  = Instant::now();
+    let result = if whole_scan( cfg ) { out.write_all( &scan_report ) }
+                 else                 { print_report( entry, cfg, &mut out ) };
+    let result = result.and_then( |_| out.flush() );
+    render_time += start.elapsed();
+    match result {
+        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => {},
+        Err(err) => eprintln!( "Couldn't write output ({:?})", err.kind() ),
+        Ok(_)    => {},
+    }
+
+    if INTERRUPTED.load( Ordering::Relaxed ) {
+        eprintln!( "Interrupted, the sizes are partial" );
+    }
+    if let Some(pct) = estimate_margin( entry.bytes ) {
+        eprintln!( "Estimated from samples of large directories, within ±{:.1}% at 95% confidence", pct );
+    }
+    if cfg.timings {
+        print_timings( scan_time, sort_time, render_time, cfg.jobs );
+    }
+}
+
+// readdir and stat times are added up over the threads, so they may take more than the scan
+fn print_timings( scan : Duration, sort : Duration, render : Duration, jobs : usize ) {
+    eprintln!( "scan {:.3}s, sort {:.3}s, render {:.3}s", scan.as_secs_f64(), sort.as_secs_f64(), render.as_secs_f64() );
+    let mut timings = TIMINGS.lock().unwrap().clone();
+    if timings.is_empty() { return }
+    timings.sort_by_key( |&( _, t )| std::cmp::Reverse( t.readdir + t.stat ) );
+    eprintln!( "{:>10} {:>10}  directory{}", "readdir", "stat", if jobs > 1 { " (summed over threads)" } else { "" } );
+    for ( name, t ) in timings {
+        eprintln!( "{:>9.3}s {:>9.3}s  {}", t.readdir.as_secs_f64(), t.stat.as_secs_f64(), name );
+    }
+}
+
+// entries and size found, time taken, then extra and the directory being read
+fn scan_status( sizes : Sizes, extra : &str ) -> String {
+    let elapsed = STARTED.lock().unwrap().map_or( 0, |start| start.elapsed().as_secs() );
+    let dir     = SCANNING.lock().unwrap().as_ref().map_or( String::new(), |p| p.display().to_string() );
+    format!( "{} entries, {}, {}s{}, {}", SCANNED.load( Ordering::Relaxed ),
+             fmt_size_str( FOUND.load( Ordering::Relaxed ), sizes ), elapsed, extra, dir )
+}
+
+// a line on stderr that follows the scan, gone once it is done
+struct Progress {
+    done   : Arc<AtomicBool>,
+    thread : thread::JoinHandle<()>,
+}
+
+impl Progress {
+    fn start( sizes : Sizes, expected : Option<u64> ) -> Progress {
+        PROGRESS.store( true, Ordering::Relaxed );
+        let done  = Arc::new( AtomicBool::new( false ) );
+        let start = Instant::now();
+        let stop  = done.clone();
+        let thread = thread::spawn( move || {
+            while !stop.load( Ordering::Relaxed ) {
+                let scanned = SCANNED.load( Ordering::Relaxed );
+
+                // what is left goes at the pace so far
+                let eta = match expected {
+                    Some(total) if scanned > 0 => {
+                        let left = total.saturating_sub( scanned );
+                        format!( ", ETA {}s", ( start.elapsed().as_secs_f64() * left as f64 / scanned as f64 ).ceil() )
+                    },
+                    _ => String::new(),
+                };
+                let line = scan_status( sizes, &eta );
+                let width = terminal_size().map_or( DEF_WIDTH, |( Width(w), Height(_h) )| w ) as usize;
+                eprint!( "\r{}\x1b[K", truncate_name( &line, width.saturating_sub( 1 ), '…' ) );
+                thread::park_timeout( std::time::Duration::from_millis( 100 ) );
+            }
+            eprint!( "\r\x1b[K" );
+        } );
+        Progress { done, thread }
+    }
+
+    fn stop( self ) {
+        self.done.store( true, Ordering::Relaxed );
+        self.thread.thread().unpark();
+        self.thread.join().ok();
+        PROGRESS.store( false, Ordering::Relaxed );
+    }
+}
+
+// how many entries the scan will go through, from the snapshot it is about to replace,
+// or from the inodes in use when whole filesystems are scanned
+fn expected_entries( cfg : &Config ) -> Option<u64> {
+    if let Some(ref file) = cfg.save {
+        if let Ok(( _, entries )) = open_snapshot( file ) {
+            return Some( entries );
+        }
+    }
+    inodes_in_use( cfg.paths.iter().map( |path| filesystem_at( path ) ) )
+}
+
+// the device of the filesystem mounted at path, with its inodes and those free, None if no mount point is there
+fn filesystem_at( path : &Path ) -> Option<( u64, u64, u64 )> {
+    let parent = path.canonicalize().ok().and_then( |p| p.parent().map( Path::to_path_buf ) );
+    let dev    = try_dev_ino_from_path( path, true ).0;
+    let mount  = parent.is_none_or( |p| try_dev_ino_from_path( &p, true ).0 != dev );
+    if !mount { return None }
+
+    let cpath = std::ffi::CString::new( path.as_os_str().as_bytes() ).ok()?;
+    let mut vfs : libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs( cpath.as_ptr(), &mut vfs ) } != 0 { return None }
+    Some( ( dev, vfs.f_files as u64, vfs.f_ffree as u64 ) )
+}
+
+// the inodes in use in the filesystems, None when one is missing or has no inode count,
+// as btrfs and many FUSE and network filesystems have
+fn inodes_in_use( filesystems : impl IntoIterator<Item = Option<( u64, u64, u64 )>> ) -> Option<u64> {
+    let mut total = 0;
+    let mut devs  = HashSet::new();
+    for filesystem in filesystems {
+        let ( dev, files, free ) = filesystem?;
+        if files == 0 { return None }
+        // a filesystem given twice, like / and /., is scanned once
+        if !devs.insert( dev ) { continue }
+        total += files - free;
+    }
+    Some( total )
+}
+
+// cut and aggregate the scanned trees for the report
+fn assemble( tree : &mut Tree, cfg : &Config, depth : Option<u8> ) {
+    let roots : Vec<usize> = roots( tree.top() ).iter().map( |root| root.id ).collect();
+    for root in roots {
+        let path  = tree.node( root ).path();
+        let depth = depth.or_else( || depth_for( cfg, &path ) );
+        tree.arrange( root, cfg, depth );
+        if cfg.min_depth > 0 {
+            tree.lift( root, cfg.min_depth, cfg );
+        }
+        if cfg.collapse_flag {
+            tree.collapse( root );
+        }
+        // only the root is canonicalized, below it paths are made of real names
+        if cfg.abs_flag {
+            let abs_path = std::env::current_dir().map( |dir| dir.join( &path ) ).unwrap_or( path.clone() );
+            let abs_path = abs_path.canonicalize().unwrap_or( abs_path );
+            tree.nodes[root].path = tree.names.add( abs_path.as_os_str().as_bytes() );
+        }
+    }
+}
+
+// deepest level whose report fits the terminal, leaving a line for the prompt
+fn fit_depth( tree : &Tree, cfg : &Config ) -> u8 {
+    let height = match terminal_height() {
+        Some(h) if cfg.output.is_none() => h as usize,
+        _                               => return cfg.depth + 1,
+    };
+    let mut best  = None;
+    let mut lines = 0;
+    for ( depth, &count ) in ( 1..u8::MAX ).zip( &report_lines( tree, cfg ) ).skip( 1 ) {
+        // stop when too tall, or when there is nothing deeper to show
+        if ( count >= height && best.is_some() ) || count == lines { break }
+        best  = Some( depth );
+        lines = count;
+    }
+    best.unwrap_or( cfg.depth + 1 )
+}
+
+// the lines of the tree report at each depth from 1, counted in one walk over the tree
+// the other formats take a line for each entry as well, or aren't for reading in a terminal
+fn report_lines( tree : &Tree, cfg : &Config ) -> Vec<usize> {
+    let roots = roots( tree.top() );
+    let mut levels = Vec::new();
+    for root in &roots {
+        tree.count_lines( root.id, cfg, 0, &mut levels );
+    }
+    let per_entry = entry_lines( cfg );
+    let mut count = 1 + if cfg.footer_flag { 2 } else { 0 };
+    if tree.top().is_collection() {
+        count += roots.len() * per_entry;
+    }
+    // with --min-depth, the levels above it are lifted away
+    let mut lines = vec![ count ];
+    for ( level, n ) in levels.into_iter().enumerate() {
+        if level + 1 >= cfg.min_depth as usize {
+            count += n * per_entry;
+        }
+        lines.push( count );
+    }
+    lines
+}
+
+// with the bar below it, an entry takes two lines
+fn entry_lines( cfg : &Config ) -> usize {
+    if cfg.layout == Layout::BarBelow && !cfg.no_bar_flag { 2 } else { 1 }
+}
+
+fn print_footer( entry : &Entry, sizes : Sizes, out : &mut dyn Write ) -> io::Result<()> {
+    let t = &entry.totals;
+    writeln!( out, "{} in {} files and {} directories", fmt_size_str( entry.bytes, sizes ), t.count, t.dirs )?;
+    writeln!( out, "{} aggregated, {} excluded, {} errors", AGGREGATED.load( Ordering::Relaxed ),
+              EXCLUDED.load( Ordering::Relaxed ), ERRORS.load( Ordering::Relaxed ) )
+}
+
+fn print_report( entry : Node, cfg : &Config, out : &mut dyn Write ) -> io::Result<()> {
+    match cfg.format {
+        Format::Tree       => {
+            let twidth = if cfg.output.is_some() { DEF_WIDTH } else { terminal_width() };
+            entry.print( out, cfg, twidth )?;
+            if cfg.footer_flag {
+                print_footer( &entry, cfg.sizes, out )?;
+            }
+            Ok(())
+        },
+        Format::Csv( sep ) => {
+            let mut rows = Vec::new();
+            for root in roots( entry ) {
+                root.rows( 0, root.bytes, &mut rows );
+            }
+            writeln!( out, "path{0}depth{0}size{0}usage{0}percent", sep )?;
+            for ( path, depth, size, usage, percent ) in rows {
+                writeln!( out, "{1}{0}{2}{0}{3}{0}{4}{0}{5:.2}", sep,
+                          csv_field( &path, sep ), depth, size, usage, percent )?;
+            }
+            Ok(())
+        },
+        Format::Dot        => {
+            writeln!( out, "digraph dutree {{" )?;
+            writeln!( out, "    node [shape=box];" )?;
+            entry.print_dot( out, &mut 0, cfg.sizes )?;
+            writeln!( out, "}}" )
+        },
+        Format::Du( unit ) => {
+            for root in roots( entry ) {
+                root.print_du( out, unit, cfg.sizes )?;
+            }
+            // like du -c
+            if cfg.total_flag && entry.is_collection() {
+                let size = match cfg.sizes.units {
+                    Units::Block(_) => fmt_size_str( entry.bytes, cfg.sizes ),
+                    _               => fmt_du_size( entry.bytes, unit ),
+                };
+                writeln!( out, "{}\t{}", size, entry.name() )?;
+            }
+            Ok(())
+        },
+        Format::List       => {
+            let mut list = Vec::new();
+            for root in roots( entry ) {
+                root.flatten( &mut list );
+            }
+            // without a tree, big entries aren't needed to lead anywhere
+            list.retain( |e| e.bytes <= cfg.max_size );
+            list.sort_by( |a, b| if cfg.reverse { a.bytes.cmp( &b.bytes ) } else { b.bytes.cmp( &a.bytes ) } );
+            for e in list {
+                writeln!( out, "{:>13} {}", fmt_size_str( e.bytes, cfg.sizes ), e.path().display() )?;
+            }
+            Ok(())
+        },
+        Format::ByType | Format::ByCategory |
+        Format::AgeHistogram | Format::SizeHistogram | Format::Duplicates(_) |
+        Format::Stats | Format::ByMount | Format::BiggestDirs(_) => Ok(()), // printed from the whole scan
+        Format::Summarize  => {
+            let total = cfg.total_flag && entry.is_collection();
+            for root in roots( entry ) {
+                if total {
+                    write!( out, "{:3}% ", ( root.bytes * 100 ).checked_div( entry.bytes ).unwrap_or( 0 ) )?;
+                }
+                writeln!( out, "{:>13} {}", fmt_size_str( root.bytes, cfg.sizes ), root.path().display() )?;
+            }
+            if total {
+                writeln!( out, "100% {:>13} {}", fmt_size_str( entry.bytes, cfg.sizes ), entry.name() )?;
+            }
+            Ok(())
+        },
+        Format::Print0     => {
+            for root in roots( entry ) {
+                root.print0( out )?;
+            }
+            Ok(())
+        },
+        Format::Template( ref template ) => {
+            for root in roots( entry ) {
+                let base = if cfg.pct_root { entry.bytes } else { root.bytes };
+                root.print_template( out, template, 0, base, cfg.pct_root, cfg.sizes )?;
+            }
+            Ok(())
+        },
+        Format::Json       => {
+            // like tree, the report doesn't count the roots
+            let mut counts = ( 0, 0 );
+            writeln!( out, "[" )?;
+            for root in roots( entry ) {
+                root.print_json( out, 1, &root.path().to_string_lossy(), &mut counts )?;
+                writeln!( out, "," )?;
+            }
+            writeln!( out, "  {{\"type\":\"report\",\"directories\":{},\"files\":{}}}",
+                      counts.0, counts.1 )?;
+            writeln!( out, "]" )
+        },
+        Format::Ncdu       => {
+            let timestamp = SystemTime::now().duration_since( UNIX_EPOCH ).map( |d| d.as_secs() ).unwrap_or( 0 );
+            write!( out, "[1,0,{{\"progname\":\"dutree\",\"progver\":\"{}\",\"timestamp\":{}}}",
+                    VERSTR.trim_start_matches( 'v' ), timestamp )?;
+
+            // ncdu expects the root to be named by its full path
+            let name = if entry.is_collection() { entry.name().to_string() } else {
+                let path = entry.path();
+                let path = path.canonicalize().unwrap_or( path );
+                path.to_string_lossy().into_owned()
+            };
+            writeln!( out, "," )?;
+            entry.print_ncdu( out, &name )?;
+            writeln!( out, "]" )
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ls_colors() {
+        let mut dict = Dict::<String>::new();
+        dict.add( "di".to_string(), "dircode".to_string() );
+        dict.add( "li".to_string(), "linkcod".to_string() );
+        dict.add( "*.mp3".to_string(), "mp3code".to_string() );
+        dict.add( "*.tar".to_string(), "tarcode".to_string() );
+        assert_eq!( "dircode", color_from_path( Path::new(".")       , &dict ).unwrap() );
+        assert_eq!( "mp3code", color_from_path( Path::new("test.mp3"), &dict ).unwrap() );
+        assert_eq!( "tarcode", color_from_path( Path::new("test.tar"), &dict ).unwrap() );
+    }
+
+    #[test]
+    fn link_colors() {
+        // links lead from where they are, and short keys are no extensions
+        let mut dict = Dict::<String>::new();
+        dict.add( "ln".to_string(), "lncode".to_string() );
+        dict.add( "or".to_string(), "orcode".to_string() );
+        dict.add( "*".to_string(), "anycode".to_string() );
+        assert_eq!( "lncode", color_from_path( Path::new("test/dir3/link"), &dict ).unwrap() );
+        let link = Stat { dev : 1, ino : 2, nlink : 1, is_dir : false, mode : 0o120777, uid : 0, gid : 0, size : 7, blocks : 0,
+                          mtime : 0, link : true };
+        assert_eq!( "orcode", color_from_stat( Path::new("test/gone"), Some( &link ), &dict ).unwrap() );
+    }
+
+    #[test]
+    fn du_sizes() {
+        assert_eq!( "0"    , fmt_du_size( 0                 , 'k' ) );
+        assert_eq!( "1"    , fmt_du_size( 1                 , 'k' ) );
+        assert_eq!( "1000" , fmt_du_size( 1000              , 'h' ) );
+        assert_eq!( "1.5K" , fmt_du_size( 1536              , 'h' ) );
+        assert_eq!( "1.1K" , fmt_du_size( 1025              , 'h' ) );
+        assert_eq!( "15K"  , fmt_du_size( 15 * 1024         , 'h' ) );
+        assert_eq!( "1.0M" , fmt_du_size( 1024 * 1024 - 1   , 'h' ) );
+        assert_eq!( "2.0G" , fmt_du_size( 2 * 1024u64.pow(3), 'h' ) );
+    }
+
+    #[test]
+    fn templates() {
+        let value = |key : &str| match key {
+            "path" => Some( "a/{size}".to_string() ),
+            "size" => Some( "1 B".to_string() ),
+            _      => None,
+        };
+        assert_eq!( "a/{size}\t1 B", fmt_template( "{path}\t{size}", value ) );
+        assert_eq!( "{x} {size 1 B}", fmt_template( "{x} {size {size}}", value ) );
+    }
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!( "dir1"          , csv_field( "dir1"        , ',' ) );
+        assert_eq!( "\"a,b\""       , csv_field( "a,b"         , ',' ) );
+        assert_eq!( "a,b"           , csv_field( "a,b"         , '\t') );
+        assert_eq!( "\"say \"\"hi\"\"\"", csv_field( "say \"hi\""  , ',' ) );
+    }
+
+    #[test]
+    fn digit_groups() {
+        assert_eq!( "1,234,567 B" , group_digits( "1234567 B"  , ',', '.' ) );
+        assert_eq!( "123.45 KiB"  , group_digits( "123.45 KiB" , ',', '.' ) );
+        assert_eq!( "1.234,50 MB" , group_digits( "1234.50 MB" , '.', ',' ) );
+    }
+
+    #[test]
+    fn extended_colors() {
+        assert_eq!( Some( "#0000ee".to_string() ), svg_color( "01;34" ) );
+        assert_eq!( Some( "#d70000".to_string() ), svg_color( "38;5;160" ) );
+        assert_eq!( Some( "#102030".to_string() ), svg_color( "01;38;2;16;32;48" ) );
+        assert_eq!( Some( "#080808".to_string() ), svg_color( "48;5;33;38;5;232" ) );
+        assert_eq!( None                         , svg_color( "48;5;33" ) );
+    }
+
+    #[test]
+    fn gradient_ends() {
+        // green, yellow and red, also with 256 colors
+        assert_eq!( "38;2;0;200;0"  , gradient_color( 0  , true  ) );
+        assert_eq!( "38;2;220;0;0"  , gradient_color( 100, true  ) );
+        assert_eq!( "38;5;46"       , gradient_color( 0  , false ) );
+        assert_eq!( "38;5;226"      , gradient_color( 50 , false ) );
+        assert_eq!( "38;5;196"      , gradient_color( 100, false ) );
+    }
+
+    #[test]
+    fn name_truncation() {
+        assert_eq!( "dir1"    , truncate_name( "dir1"      , 4, '…' ) );
+        assert_eq!( "dir…"    , truncate_name( "dir12"     , 4, '…' ) );
+        assert_eq!( "日本…"   , truncate_name( "日本語.txt", 6, '…' ) );
+        assert_eq!( "日…"     , truncate_name( "日本語.txt", 4, '…' ) );
+        assert_eq!( "e\u{301}…", truncate_name( "e\u{301}e\u{301}e", 2, '…' ) );
+    }
+
+    #[test]
+    fn natural_order() {
+        use std::cmp::Ordering::*;
+        assert_eq!( Less   , natural_cmp( "log.2"  , "log.10"  ) );
+        assert_eq!( Less   , natural_cmp( "v1.9"   , "v1.10"   ) );
+        assert_eq!( Greater, natural_cmp( "v2.0"   , "v1.10"   ) );
+        assert_eq!( Equal  , natural_cmp( "a007"   , "A7"      ) );
+        assert_eq!( Less   , natural_cmp( "file"   , "file1"   ) );
+    }
+
+    #[test]
+    fn globs() {
+        let m = |p : &str, t : &str| glob_match( p.as_bytes(), t.as_bytes() );
+        assert!(  m( "*.log"      , "a.log"        ) );
+        assert!( !m( "*.log"      , "a/b.log"      ) );
+        assert!(  m( "src/**"     , "src/a/b.rs"   ) );
+        assert!(  m( "a/**/b"     , "a/b"          ) );
+        assert!(  m( "a/**/b"     , "a/x/y/b"      ) );
+        assert!(  m( "file?.[ch]" , "file1.c"      ) );
+        assert!( !m( "[!a-c]x"    , "bx"           ) );
+        assert!(  m( "[x"         , "[x"           ) );
+        assert!(  glob_matches( "node_modules", Path::new( "a/node_modules" ), false ) );
+        assert!(  glob_matches( "cache"       , Path::new( "a/CaChe" )       , true  ) );
+    }
+
+    #[test]
+    fn globs_of_invalid_names() {
+        // names that aren't UTF-8 are matched by their bytes, they only show as [invalid name]
+        let root = scratch_tree( "invalid", &[ ( "d/a.log", 10 ), ( "d/b", 20 ) ] );
+        fs::write( root.join( "d" ).join( OsStr::from_bytes( b"x\xff.log" ) ), vec![ 0u8; 40 ] ).unwrap();
+        fs::create_dir( root.join( OsStr::from_bytes( b"\xffe" ) ) ).unwrap();
+        fs::write( root.join( OsStr::from_bytes( b"\xffe/f" ) ), vec![ 0u8; 80 ] ).unwrap();
+        fs::write( root.join( OsStr::from_bytes( b".\xffh" ) ), vec![ 0u8; 160 ] ).unwrap();
+        age_dirs( &root );
+        let dir   = root.to_string_lossy().into_owned();
+        let total = |args : &[&str]| {
+            let mut argv = args.to_vec();
+            argv.extend( &[ "-S", "-b", "--type", "f", &dir ] );
+            report( &argv ).split_whitespace().next().unwrap().to_string()
+        };
+        assert_eq!( "260", total( &[ "--exclude", "*.log" ] ) );
+        assert_eq!( "270", total( &[ "--exclude-path", "d/x*" ] ) );
+        assert_eq!( "50" , total( &[ "--include", "*.log" ] ) );
+        assert_eq!( "80" , total( &[ "--include", "?e/*" ] ) );
+        assert_eq!( "150", total( &[ "--hidden", "hide" ] ) );
+    }
+
+    #[test]
+    fn cache_records() {
+        let stat = Stat { dev : 1, ino : 2, nlink : 3, is_dir : false, mode : 0o100644, uid : 10, gid : 11, size : 4, blocks : 8,
+                          mtime : -5, link : false };
+        let list = vec![ ( PathBuf::from( "a" ), Some( false ), Some( stat ) ), ( PathBuf::from( "b" ), None, None ) ];
+        let dir  = Stat { dev : 6, ino : 7, nlink : 2, is_dir : true, mode : 0o40755, uid : 0, gid : 0, size : 4096, blocks : 8,
+                          mtime : 9, link : false };
+        let mut dirs = HashMap::new();
+        dirs.insert( PathBuf::from( "/x/y" ), CachedDir { stat : dir, list, seen : true } );
+        let mut buf = Vec::new();
+        write_cache( &dirs.iter().collect::<Vec<_>>(), &mut buf ).unwrap();
+
+        let dirs = read_cache( &mut &buf[..] ).unwrap();
+        let dir  = &dirs[ Path::new( "/x/y" ) ];
+        assert_eq!( ( 6, 7, true, 9 ), ( dir.stat.dev, dir.stat.ino, dir.stat.is_dir, dir.stat.mtime ) );
+        assert_eq!( Some( false ), dir.list[0].1 );
+        assert_eq!( ( 10, 11, 4, 8, -5 ), dir.list[0].2.map( |s| ( s.uid, s.gid, s.size, s.blocks, s.mtime ) ).unwrap() );
+        assert_eq!( ( None, None ), ( dir.list[1].1, dir.list[1].2.map( |s| s.size ) ) );
+        assert!( read_cache( &mut &b"DUTREE\x03"[..] ).is_err() );
+    }
+
+    // runs share the counters of the scan, so they go one at a time
+    static RUNS : Mutex<()> = Mutex::new( () );
+
+    // the report of dutree with these arguments, written to a file so it has no colors and a fixed width
+    fn report( args : &[&str] ) -> String {
+        let _run = RUNS.lock().unwrap_or_else( |err| err.into_inner() );
+        let out  = scratch( "report" );
+        let mut argv = vec![ "dutree".to_string(), "-o".to_string(), out.to_string_lossy().into_owned() ];
+        argv.extend( args.iter().map( |arg| arg.to_string() ) );
+        match Config::from_args( argv ) {
+            XOk(mut cfg) => {
+                cfg.cache_home = Some( cache_home() );
+                run( &cfg )
+            },
+            _            => panic!( "invalid arguments {:?}", args ),
+        }
+        fs::read_to_string( &out ).unwrap()
+    }
+
+    // with --cache, the tests keep their own, a file for each of their trees
+    fn cache_home() -> PathBuf {
+        env::temp_dir().join( format!( "dutree-cache-home-{}", std::process::id() ) )
+    }
+
+    // the tests that write there take turns, the last one out would take it away from the others
+    static CACHES : Mutex<()> = Mutex::new( () );
+
+    fn parses( args : &[&str] ) -> bool {
+        let argv = std::iter::once( "dutree" ).chain( args.iter().cloned() ).map( String::from ).collect();
+        matches!( Config::from_args( argv ), XOk(_) )
+    }
+
+    #[test]
+    fn format_conflicts() {
+        assert!(  parses( &[ "--csv", "test" ] ) );
+        assert!( !parses( &[ "--csv", "-S", "test" ] ) );
+        assert!( !parses( &[ "--by-type", "--list", "test" ] ) );
+        assert!( !parses( &[ "--biggest-dirs", "3", "--du", "test" ] ) );
+        assert!( !parses( &[ "--stats", "--footer", "test" ] ) );
+    }
+
+    #[test]
+    fn du_usage() {
+        let usage = |args : &[&str]| {
+            let argv = std::iter::once( "dutree" ).chain( args.iter().cloned() ).map( String::from ).collect();
+            match Config::from_args( argv ) { XOk(cfg) => cfg.usage_flag, _ => panic!( "invalid arguments {:?}", args ), }
+        };
+        assert!(  usage( &[ "--du", "test" ] ) );
+        assert!( !usage( &[ "--du", "--apparent-size", "test" ] ) );
+        assert!( !usage( &[ "--du", "-b", "test" ] ) );
+        assert!( !usage( &[ "test" ] ) );
+        assert!( !parses( &[ "-u", "--apparent-size", "test" ] ) );
+        assert_eq!( "256\ttest/dir1/file1\n", report( &[ "--du", "-b", "test/dir1/file1" ] ) );
+    }
+
+    #[test]
+    fn biggest_dirs_below_depth() {
+        assert_eq!( "       1536 B test/dir1/test3\n       1280 B test/dir1/test3/test2\n        768 B test/dir1/test2\n",
+                    report( &[ "--biggest-dirs", "3", "--type", "f", "-b", "-d1", "test/dir1" ] ) );
+        // the paths given are left out, those without directories below add nothing
+        assert_eq!( report( &[ "--biggest-dirs", "9", "--type", "f", "-b", "test/dir1" ] ),
+                    report( &[ "--biggest-dirs", "9", "--type", "f", "-b", "test/dir3", "test/dir1", "test/dir2" ] ) );
+    }
+
+    #[test]
+    fn perms_of_links() {
+        for args in &[ &[ "--perms", "-d1", "test/dir3" ][..], &[ "-L", "--perms", "-d1", "test/dir3" ][..] ] {
+            let perms = report( args );
+            assert!( perms.lines().any( |line| line.contains( "link" ) && line.ends_with( " lrwxrwxrwx" ) ), "{}", perms );
+        }
+    }
+
+    #[test]
+    fn link_targets() {
+        let links = report( &[ "--link-targets", "--name-width", "30", "-d1", "test/dir3" ] );
+        assert!( links.contains( "link -> ../dir2 " ), "{}", links );
+        assert!( links.contains( "file1 " ) && !links.contains( "file1 ->" ), "{}", links );
+    }
+
+    #[test]
+    fn deepest_depth() {
+        // 255 levels show everything, however the depth is given
+        let deep = report( &[ "-d", "9", "--type", "f", "-b", "test/dir1" ] );
+        for args in &[ &[ "-d", "255", "test/dir1" ][..], &[ "--depth-for", "test/dir1=255", "test/dir1" ], &[ "test/dir1:255" ] ] {
+            assert_eq!( deep, report( &[ &[ "--type", "f", "-b" ][..], args ].concat() ), "{:?}", args );
+        }
+    }
+
+    #[test]
+    fn low_mem_depth() {
+        let low_mem = report( &[ "--low-mem", "--type", "f", "-b", "test/dir1" ] );
+        assert_eq!( report( &[ "-d1", "--type", "f", "-b", "test/dir1" ] ), low_mem );
+        assert!( low_mem.starts_with( "[ dir1 2816 B ]\n" ) && low_mem.lines().count() == 5, "{}", low_mem );
+        assert_eq!( report( &[ "-d2", "--type", "f", "-b", "test/dir1" ] ),
+                    report( &[ "--low-mem", "-d2", "--type", "f", "-b", "test/dir1" ] ) );
+    }
+
+    #[test]
+    fn low_mem_counts_links_once() {
+        let root = scratch_tree( "low-mem", &[ ( "a/f", 1000 ), ( "b/g", 10 ) ] );
+        fs::hard_link( root.join( "a/f" ), root.join( "b/f" ) ).unwrap();
+        std::os::unix::fs::symlink( "..", root.join( "a/up" ) ).unwrap();
+        age_dirs( &root );
+        let dir  = root.to_string_lossy().into_owned();
+        let head = |args : &[&str]| {
+            let mut argv = args.to_vec();
+            argv.extend( &[ "--type", "f", "-b", &dir ] );
+            report( &argv ).lines().next().unwrap().split_whitespace().nth( 2 ).unwrap().to_string()
+        };
+        // the same totals as without it, hard links and directories reached again count once
+        assert_eq!( "1010", head( &[ "-d1" ] ) );
+        assert_eq!( "1010", head( &[ "--low-mem" ] ) );
+        assert_eq!( "2010", head( &[ "-d1", "-l" ] ) );
+        assert_eq!( "1010", head( &[ "-d1", "-L" ] ) );
+        assert_eq!( "1010", head( &[ "--low-mem", "-L" ] ) );
+        assert_eq!( "1010", head( &[ "--low-mem", "-L", "-j1" ] ) );
+    }
+
+    #[test]
+    fn expected_entries_per_filesystem() {
+        let expected = |paths : &[&str]| {
+            let argv = std::iter::once( "dutree" ).chain( paths.iter().cloned() ).map( String::from ).collect();
+            match Config::from_args( argv ) {
+                XOk(cfg) => expected_entries( &cfg ),
+                _        => panic!( "invalid arguments {:?}", paths ),
+            }
+        };
+        assert_eq!( None, expected( &[ "test" ] ) );
+
+        assert_eq!( Some( 100 ), inodes_in_use( vec![ Some( ( 1, 100, 40 ) ), Some( ( 1, 100, 40 ) ), Some( ( 2, 50, 10 ) ) ] ) );
+        assert_eq!( None, inodes_in_use( vec![ Some( ( 1, 100, 40 ) ), None ] ) );
+        assert_eq!( None, inodes_in_use( vec![ Some( ( 1, 100, 40 ) ), Some( ( 2, 0, 0 ) ) ] ) );
+    }
+
+    #[test]
+    fn fit_lines() {
+        let file = scratch( "fit" );
+        let load = file.to_string_lossy().into_owned();
+        for args in &[ &[][..], &[ "--aggr=1K" ], &[ "--top", "2" ], &[ "--min-depth", "1" ], &[ "--footer", "--prune-empty" ],
+                       &[ "--layout", "bar-below" ], &[ "--min-size", "300" ], &[ "--total" ] ] {
+            // the roots of more paths go under a collection
+            let paths : &[&str] = if args.contains( &"--total" ) { &[ "test/dir1", "test/dir3" ] } else { &[ "test" ] };
+            report( &[ &[ "--save", &load, "-b" ][..], paths ].concat() );
+            let argv = std::iter::once( "dutree" ).chain( args.iter().cloned() ).chain( [ "-b", "--load", &load ] )
+                                                  .map( String::from ).collect();
+            let cfg  = match Config::from_args( argv ) { XOk(cfg) => cfg, _ => panic!( "invalid arguments {:?}", args ) };
+            let tree = read_snapshot( &file, &cfg ).unwrap();
+            let lines = report_lines( &tree, &cfg );
+            assert!( lines.len() > 4, "{:?}", lines );
+            // -d shows as many levels below the root, arrange counts the root too
+            for ( depth, &count ) in lines.iter().enumerate() {
+                let depth  = depth.to_string();
+                let mut argv = args.to_vec();
+                argv.extend( &[ "-d", &depth, "-b", "--load", &load ] );
+                assert_eq!( count, report( &argv ).lines().count(), "{:?} -d {}", args, depth );
+            }
+        }
+    }
+
+    #[test]
+    fn max_lines_fill() {
+        for args in &[ &[][..], &[ "--layout", "bar-below" ], &[ "--footer" ], &[ "--layout", "bar-below", "--footer" ] ] {
+            let per_entry = if args.contains( &"bar-below" ) { 2 } else { 1 };
+            // the header and the footer are always there, the entries take what is left
+            for n in 3..=12 {
+                let max  = n.to_string();
+                let mut argv = args.to_vec();
+                argv.extend( &[ "--max-lines", &max, "-d", "9", "-b", "test" ] );
+                let lines = report( &argv ).lines().count();
+                assert!( lines <= n && lines + per_entry > n, "{:?} --max-lines {}: {} lines", args, n, lines );
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_header() {
+        let file = scratch( "snapshot" );
+        let saved = report( &[ "--save", &file.to_string_lossy(), "--type", "f", "-b", "test/dir1" ] );
+        // the 9 files, directories don't pass --type f
+        assert_eq!( 9, open_snapshot( &file ).unwrap().1 );
+        assert_eq!( saved, report( &[ "--load", &file.to_string_lossy(), "-b" ] ) );
+
+        // the whole tree is kept, to be shown at any depth and arranged any way
+        report( &[ "--save", &file.to_string_lossy(), "test" ] );
+        for args in &[ &[ "-d3" ][..], &[ "-d2", "--aggr=1K" ], &[ "-d4", "--sort", "name", "--top", "2" ], &[ "--list", "-u" ] ] {
+            assert_eq!( report( &[ args, &[ "test" ][..] ].concat() ),
+                        report( &[ args, &[ "--load", &file.to_string_lossy() ][..] ].concat() ), "{:?}", args );
+        }
+    }
+
+    // a path in the temp directory, removed once the test is done with it, also when the test fails
+    struct Scratch( PathBuf );
+
+    impl Drop for Scratch {
+        fn drop( &mut self ) {
+            fs::remove_dir_all( &self.0 ).or_else( |_| fs::remove_file( &self.0 ) ).ok();
+        }
+    }
+
+    impl std::ops::Deref for Scratch {
+        type Target = Path;
+        fn deref( &self ) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for Scratch {
+        fn as_ref( &self ) -> &Path {
+            &self.0
+        }
+    }
+
+    // what an earlier run left there goes first
+    fn scratch( name : &str ) -> Scratch {
+        let scratch = Scratch( env::temp_dir().join( format!( "dutree-{}-{}", name, std::process::id() ) ) );
+        fs::remove_dir_all( &scratch ).or_else( |_| fs::remove_file( &scratch ) ).ok();
+        scratch
+    }
+
+    // a tree to change between scans, its directories an hour old so the cache takes them
+    fn scratch_tree( name : &str, files : &[(&str, usize)] ) -> Scratch {
+        let root = scratch( name );
+        for &( file, size ) in files {
+            let file = root.join( file );
+            fs::create_dir_all( file.parent().unwrap() ).unwrap();
+            fs::write( &file, vec![ 0u8; size ] ).unwrap();
+        }
+        age_dirs( &root );
+        root
+    }
+
+    fn age_dirs( dir : &Path ) {
+        for entry in fs::read_dir( dir ).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() { age_dirs( &path ) }
+        }
+        let hour_ago = SystemTime::now() - Duration::from_secs( 3600 );
+        fs::File::open( dir ).unwrap().set_modified( hour_ago ).unwrap();
+    }
+
+    #[test]
+    fn cache_reuse() {
+        let _home = CACHES.lock().unwrap_or_else( |err| err.into_inner() );
+        let _home = Scratch( cache_home() );
+        let root  = scratch_tree( "cache", &[ ( "a/b/f", 1000 ), ( "a/g", 100 ) ] );
+        let a     = root.join( "a" ).to_string_lossy().into_owned();
+        let total = |args : &[&str]| {
+            let mut argv = args.to_vec();
+            argv.extend( &[ "-S", "-b", "--type", "f", &a ] );
+            report( &argv ).split_whitespace().next().unwrap().to_string()
+        };
+
+        assert_eq!( "1100", total( &[ "--cache" ] ) );
+        let cache = cache_file( &cache_home(), Path::new( &a ) );
+        assert!( cache.exists() );
+
+        // a file grown in place is taken as it was, until the cache is refreshed
+        fs::OpenOptions::new().append( true ).open( root.join( "a/b/f" ) ).unwrap().write_all( &[ 0; 500 ] ).unwrap();
+        age_dirs( &root );
+        assert_eq!( "1100", total( &[ "--cache" ] ) );
+
+        // --no-cache neither reads nor writes it, also after --cache
+        let kept = fs::read( &cache ).unwrap();
+        assert_eq!( "1600", total( &[ "--no-cache" ] ) );
+        assert_eq!( "1600", total( &[ "--cache", "--no-cache" ] ) );
+        assert_eq!( kept, fs::read( &cache ).unwrap() );
+        assert_eq!( "1100", total( &[ "--cache" ] ) );
+
+        // --refresh rewrites it with what it finds
+        assert_eq!( "1600", total( &[ "--refresh" ] ) );
+        assert_ne!( kept, fs::read( &cache ).unwrap() );
+        assert_eq!( "1600", total( &[ "--cache" ] ) );
+
+        // a new file changes the mtime of its directory
+        fs::write( root.join( "a/b/h" ), vec![ 0u8; 10 ] ).unwrap();
+        assert_eq!( "1610", total( &[ "--cache" ] ) );
+
+        // and a directory made again has another inode, whatever its mtime
+        fs::remove_dir_all( root.join( "a/b" ) ).unwrap();
+        fs::create_dir( root.join( "a/b" ) ).unwrap();
+        fs::write( root.join( "a/b/f" ), vec![ 0u8; 1000 ] ).unwrap();
+        age_dirs( &root );
+        assert_eq!( "1100", total( &[ "--cache" ] ) );
+    }
+
+    #[test]
+    fn incremental_nested_change() {
+        let _home = CACHES.lock().unwrap_or_else( |err| err.into_inner() );
+        let _home = Scratch( cache_home() );
+        let root  = scratch_tree( "incremental", &[ ( "a/f", 20000 ), ( "a/b/g", 1000 ), ( "a/b/c/h", 384 ) ] );
+        let a     = root.join( "a" ).to_string_lossy().into_owned();
+        let total = |depth : &str| {
+            let scan = report( &[ "--incremental", depth, "-b", "--type", "f", &a ] );
+            scan.lines().next().unwrap().to_string()
+        };
+        for depth in &[ "-d1", "-d3" ] {
+            assert_eq!( "[ a 21384 B ]", total( depth ) );
+        }
+
+        // only c changed, below the unchanged a and b
+        fs::write( root.join( "a/b/c/big" ), vec![ 0u8; 90000 ] ).unwrap();
+        for depth in &[ "-d1", "-d3", "-d1" ] {
+            assert_eq!( "[ a 111384 B ]", total( depth ) );
+        }
+
+        // files of the levels shown are taken from the cache too, only with --incremental
+        fs::OpenOptions::new().append( true ).open( root.join( "a/f" ) ).unwrap().write_all( &[ 0; 1000 ] ).unwrap();
+        age_dirs( &root );
+        assert_eq!( "[ a 111384 B ]", total( "-d1" ) );
+        assert!( report( &[ "--cache", "-d1", "-b", "--type", "f", &a ] ).starts_with( "[ a 112384 B ]" ) );
+
+        // the scans without it drop them, --refresh too, though the two don't go together
+        assert_eq!( "[ a 112384 B ]", total( "-d1" ) );
+        fs::OpenOptions::new().append( true ).open( root.join( "a/f" ) ).unwrap().write_all( &[ 0; 1000 ] ).unwrap();
+        age_dirs( &root );
+        assert_eq!( "[ a 112384 B ]", total( "-d1" ) );
+        assert!( report( &[ "--refresh", "-d1", "-b", "--type", "f", &a ] ).starts_with( "[ a 113384 B ]" ) );
+        assert_eq!( "[ a 113384 B ]", total( "-d1" ) );
+        assert!( !parses( &[ "--incremental", "--refresh", &a ] ) );
+        assert!( !parses( &[ "--incremental", "--no-cache", &a ] ) );
+    }
+
+    #[test]
+    fn filters_matching_nothing() {
+        assert_eq!( "[ test 0 B ]\n", report( &[ "--ext", "xyz", "test" ] ) );
+        let mp3 = report( &[ "--ext", "mp3", "-d1", "test" ] );
+        assert!( mp3.starts_with( "[ test 0 B ]\n`- file.mp3 " ), "{}", mp3 );
+        assert!( mp3.contains( "|   0%" ), "{}", mp3 );
+    }
+
+    #[test]
+    fn scan_totals() {
+        assert_eq!( "       2816 B test/dir1\n        768 B test/dir2\n        256 B test/dir3\n",
+                    report( &[ "-S", "-b", "--type", "f", "test/dir1", "test/dir2", "test/dir3" ] ) );
+        // the links themselves, not what they lead to
+        assert_eq!( "         26 B test\n", report( &[ "-S", "-b", "--type", "l", "test" ] ) );
+    }
+
+    #[test]
+    fn type_and_ext_filters() {
+        assert_eq!( "          0 B test\n          0 B test/file.mp3\n          0 B test/file.avi\n",
+                    report( &[ "--list", "-b", "--ext", "mp3,avi", "test" ] ) );
+        let rest = report( &[ "--list", "-b", "--not-ext", "mp3,avi,exe,tar,png", "--type", "f", "test" ] );
+        assert!( rest.starts_with( "      12544 B test\n       8192 B test/file_sparse\n" ), "{}", rest );
+        assert!( !rest.contains( ".mp3" ) && !rest.contains( "picture.png" ), "{}", rest );
+    }
+
+    #[test]
+    fn parallel_scan() {
+        let scan = |jobs : &str| report( &[ "-j", jobs, "--sort", "name", "--format", "{path} {bytes}", "-b", "test" ] );
+        let single = scan( "1" );
+        assert!( single.lines().any( |line| line == "test/dir1/test3/test2/test2/file1 256" ), "{}", single );
+        for jobs in &[ "2", "8" ] {
+            assert_eq!( single, scan( jobs ) );
+        }
+    }
+
+    #[test]
+    fn parallel_links() {
+        let own : Vec<String> = ( 0..8 ).map( |i| format!( "d{}/own", i ) ).collect();
+        let files : Vec<( &str, usize )> = own.iter().map( |file| ( file.as_str(), 10 ) ).chain( [ ( "z/shared/f", 1000 ) ] ).collect();
+        let root = scratch_tree( "parallel-links", &files );
+        for i in 0..8 {
+            fs::hard_link( root.join( "z/shared/f" ), root.join( format!( "d{}/f", i ) ) ).unwrap();
+        }
+        std::os::unix::fs::symlink( "../z/shared", root.join( "d3/shared" ) ).unwrap();
+        age_dirs( &root );
+        let dir  = root.to_string_lossy().into_owned();
+        let scan = |args : &[&str]| {
+            report( &[ args, &[ "-L", "--sort", "name", "--format", "{path} {bytes}", "-b", &dir ] ].concat() )
+        };
+        // the first path in order has them, wherever the threads got first
+        let single = scan( &[ "-j1" ] );
+        for line in &[ "/d0/f 1000\n", "/d1/f 0\n", "/d3/shared/f 0\n", "/z/shared " ] {
+            assert!( single.contains( line ), "{}", single );
+        }
+        assert!( !single.contains( "/z/shared/f" ), "{}", single );
+        // and what is left without any file matching is left out
+        let files = scan( &[ "-j1", "--type", "f" ] );
+        assert!( files.contains( "/d0/f 1000\n" ) && !files.contains( "/d1/f" ) && !files.contains( "/z" ), "{}", files );
+        for _ in 0..10 {
+            assert_eq!( single, scan( &[ "-j8" ] ) );
+            assert_eq!( files, scan( &[ "-j8", "--type", "f" ] ) );
+        }
+    }
+
+    #[test]
+    fn json_output() {
+        assert_eq!( "[\n  {\"type\":\"directory\",\"name\":\"test/dir1/test2\",\"size\":768,\"contents\":[\n    \
+                     {\"type\":\"file\",\"name\":\"file1\",\"size\":512},\n    \
+                     {\"type\":\"file\",\"name\":\"file2\",\"size\":256}\n  ]},\n  \
+                     {\"type\":\"report\",\"directories\":0,\"files\":2}\n]\n",
+                    report( &[ "--json", "-b", "--type", "f", "test/dir1/test2" ] ) );
+    }
+
+    #[test]
+    fn du_output() {
+        assert_eq!( "512\ttest/dir1/test2/file1\n256\ttest/dir1/test2/file2\n768\ttest/dir1/test2\n",
+                    report( &[ "--du", "-b", "--type", "f", "test/dir1/test2" ] ) );
+    }
+
+    #[test]
+    fn template_output() {
+        assert_eq!( "0 test2 768\n1 file1 512\n1 file2 256\n",
+                    report( &[ "--format", "{depth} {name} {bytes}", "-b", "--type", "f", "test/dir1/test2" ] ) );
+    }
+
+    #[test]
+    fn list_output() {
+        assert_eq!( "        768 B test/dir1/test2\n        512 B test/dir1/test2/file1\n        256 B test/dir1/test2/file2\n",
+                    report( &[ "--list", "-b", "--type", "f", "test/dir1/test2" ] ) );
+    }
+
+    #[test]
+    fn csv_output() {
+        let csv = report( &[ "--csv", "-b", "--type", "f", "test/dir1/test2" ] );
+        assert!( csv.starts_with( "path,depth,size,usage,percent\ntest/dir1/test2,0,768," ), "{}", csv );
+        assert!( csv.lines().any( |line| line.starts_with( "test/dir1/test2/file1,1,512," ) && line.ends_with( ",66.67" ) ), "{}", csv );
+    }
+
+    #[test]
+    fn output_file_is_ascii() {
+        // report() writes with -o, and the names are cut to show the mark too
+        let tree = report( &[ "-d3", "--name-width", "6", "test" ] );
+        assert!( tree.starts_with( "[ test " ) && tree.contains( "`- " ) && tree.contains( "~ " ), "{}", tree );
+        assert!( tree.is_ascii(), "{}", tree );
+    }
+
+    #[test]
+    fn arena_chunks() {
+        assert_eq!( ( 0, 0 ), Arena::locate( 0 ) );
+        assert_eq!( ( 0, 63 ), Arena::locate( 63 ) );
+        assert_eq!( ( 1, 0 ), Arena::locate( 64 ) );
+        assert_eq!( ( 2, 0 ), Arena::locate( 192 ) );
+        let mut arena = Arena::default();
+        arena.extend( ( 0..1000 ).map( |i| Entry { name : 0, path : NO_NAME, parent : NO_PARENT, bytes : i, totals : Totals::default(),
+                                                   dev : 0, ino : 0, mode : 0, uid : 0, gid : 0, target : NO_NAME, color : None, aggregated : false, entries : None } ) );
+        assert_eq!( 1000, arena.len() );
+        assert_eq!( 5, arena.chunks.len() );
+        assert!( arena.range( 60, 10 ).map( |e| e.bytes ).eq( 60..70 ) );
+        arena[500].bytes = 7;
+        assert_eq!( 7, arena.into_iter().nth( 500 ).unwrap().bytes );
+    }
+
+    #[test]
+    fn json_types_as_scanned() {
+        // the types come from the scan, not from what is on disk when the tree is printed
+        let root = scratch_tree( "json", &[ ( "a", 10 ) ] );
+        std::os::unix::fs::symlink( "a", root.join( "l" ) ).unwrap();
+        let file = Scratch( root.with_extension( "snap" ) );
+        report( &[ "--save", &file.to_string_lossy(), &root.to_string_lossy() ] );
+        fs::remove_file( root.join( "a" ) ).unwrap();
+        fs::create_dir( root.join( "a" ) ).unwrap();
+        fs::remove_file( root.join( "l" ) ).unwrap();
+        let json = report( &[ "--json", "--load", &file.to_string_lossy() ] );
+        assert!( json.contains( "{\"type\":\"file\",\"name\":\"a\"" ), "{}", json );
+        assert!( json.contains( "{\"type\":\"link\",\"name\":\"l\",\"target\":\"a\"" ), "{}", json );
+        let links = report( &[ "--link-targets", "--load", &file.to_string_lossy() ] );
+        assert!( links.contains( "l -> a " ), "{}", links );
+
+        // a du listing only knows what has contents is a directory
+        let du = scratch( "json-du" );
+        fs::write( &du, "7\ttest/dir3/link\n7\ttest/dir3\n" ).unwrap();
+        let json = report( &[ "--json", "--import-du", &du.to_string_lossy() ] );
+        assert!( json.contains( "{\"type\":\"file\",\"name\":\"link\"" ), "{}", json );
+    }
+
+    #[test]
+    fn ncdu_types_as_scanned() {
+        let root = scratch_tree( "ncdu", &[ ( "d/f", 10 ), ( "a", 10 ) ] );
+        let file = Scratch( root.with_extension( "snap" ) );
+        report( &[ "--save", &file.to_string_lossy(), &root.to_string_lossy() ] );
+        fs::remove_dir_all( root.join( "d" ) ).unwrap();
+        fs::write( root.join( "d" ), b"" ).unwrap();
+        // d is cut at the depth, and still a directory as it was scanned
+        let ncdu = report( &[ "--ncdu", "-d1", "--load", &file.to_string_lossy() ] );
+        assert!( ncdu.contains( "[{\"name\":\"d\"" ), "{}", ncdu );
+        assert!( ncdu.contains( "\n{\"name\":\"a\"" ), "{}", ncdu );
+    }
+
+    #[test]
+    fn hyperlinks_as_scanned() {
+        let root = scratch_tree( "links", &[ ( "a", 10 ), ( "b", 1 ), ( "c", 1 ) ] );
+        let file = Scratch( root.with_extension( "snap" ) );
+        report( &[ "--save", &file.to_string_lossy(), &root.to_string_lossy() ] );
+        fs::remove_file( root.join( "a" ) ).unwrap();
+        let links = report( &[ "--hyperlinks", "-b", "--aggr=5", "--name-width", "30", "--load", &file.to_string_lossy() ] );
+        let url = file_url( &hostname(), &root.join( "a" ) );
+        assert!( links.contains( &format!( "\x1b]8;;{}\x1b\\a\x1b]8;;\x1b\\", url ) ), "{}", links );
+        // what the report made up has nothing to link to
+        assert!( links.contains( "`- <aggregated 2 items> " ), "{}", links );
+        assert_eq!( 1, links.matches( "\x1b]8;;file:" ).count(), "{}", links );
+    }
+
+    #[test]
+    fn duplicates_as_scanned() {
+        let root = scratch_tree( "dups", &[ ( "a", 10 ), ( "d/b", 10 ), ( "c", 7 ) ] );
+        std::os::unix::fs::symlink( "0123456789", root.join( "l" ) ).unwrap();
+        let file = Scratch( root.with_extension( "snap" ) );
+        report( &[ "--save", &file.to_string_lossy(), &root.to_string_lossy() ] );
+        fs::remove_file( root.join( "a" ) ).unwrap();
+        // the link is as long as the files, but only the files are duplicates
+        let dups = report( &[ "--duplicates", "-b", "--load", &file.to_string_lossy() ] );
+        assert_eq!( dups, format!( "[ 2 x 10 B, 10 B reclaimable ]\n{}\n{}\n10 B reclaimable in 1 sets\n",
+                                   root.join( "a" ).display(), root.join( "d/b" ).display() ) );
+    }
+
+    #[test]
+    fn treemap_layout() {
+        let root = scratch_tree( "treemap", &[ ( "a", 300 ), ( "b/c", 100 ) ] );
+        let svg  = Scratch( root.with_extension( "svg" ) );
+        report( &[ "--treemap", &svg.to_string_lossy(), "-b", "--type", "f", &root.to_string_lossy() ] );
+        let map = fs::read_to_string( &svg ).unwrap();
+        assert!( map.starts_with( "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"1280\" height=\"800\"" ) && map.ends_with( "</svg>\n" ), "{}", map );
+        // the areas follow the sizes, c inside b under its name
+        assert!( map.contains( "<title>a 300 B</title><rect x=\"2.0\" y=\"14.0\" width=\"957.0\" height=\"784.0\"" ), "{}", map );
+        assert!( map.contains( "<title>b 100 B</title><rect x=\"959.0\" y=\"14.0\" width=\"319.0\" height=\"784.0\"" ), "{}", map );
+        assert!( map.contains( "<title>c 100 B</title><rect x=\"961.0\" y=\"28.0\" width=\"315.0\" height=\"768.0\"" ), "{}", map );
+    }
+
+    #[test]
+    fn dot_output() {
+        let root = scratch_tree( "dot", &[ ( "a", 300 ), ( "b/c", 100 ), ( "q\"x\\y", 5 ) ] );
+        let dot  = r#"digraph dutree {
+    node [shape=box];
+    n0 [label="ROOT\n405 B"];
+    n1 [label="a\n300 B"];
+    n0 -> n1;
+    n2 [label="b\n100 B"];
+    n3 [label="c\n100 B"];
+    n2 -> n3;
+    n0 -> n2;
+    n4 [label="q\"x\\y\n5 B"];
+    n0 -> n4;
+}
+"#;
+        assert_eq!( dot.replace( "ROOT", &root.file_name().unwrap().to_string_lossy() ),
+                    report( &[ "--dot", "-b", "--type", "f", &root.to_string_lossy() ] ) );
+    }
+
+    #[test]
+    fn prometheus_output() {
+        let root = scratch_tree( "prometheus", &[ ( "a", 300 ), ( "b/c", 100 ), ( "q\"x\\y", 5 ) ] );
+        let prom = Scratch( root.with_extension( "prom" ) );
+        report( &[ "--prometheus", &prom.to_string_lossy(), "-b", "--type", "f", &root.to_string_lossy() ] );
+        let metrics = r#"# HELP dutree_bytes Size of files and directories as reported by dutree.
+# TYPE dutree_bytes gauge
+dutree_bytes{path="ROOT"} 405
+dutree_bytes{path="ROOT/a"} 300
+dutree_bytes{path="ROOT/b"} 100
+dutree_bytes{path="ROOT/b/c"} 100
+dutree_bytes{path="ROOT/q\"x\\y"} 5
+"#;
+        assert_eq!( metrics.replace( "ROOT", &root.to_string_lossy() ), fs::read_to_string( &prom ).unwrap() );
+    }
+
+    #[test]
+    fn du_import() {
+        let du = scratch( "import-du" );
+        // directories after what they hold, as du lists them, the last of repeated lines counts
+        fs::write( &du, "300\tx/a\n100\tx/b/c\n100\tx/b\n7\tx/b/c\n405\tx\nnot a line\n" ).unwrap();
+        assert_eq!( "        405 B x\n        300 B x/a\n        100 B x/b\n          7 B x/b/c\n",
+                    report( &[ "--list", "-b", "--import-du", &du.to_string_lossy() ] ) );
+        let tree = report( &[ "-b", "--import-du", &du.to_string_lossy() ] );
+        assert!( tree.starts_with( "[ x 405 B ]\n|- a " ) && tree.contains( "\n`- b " ) && tree.contains( "\n   `- c " ), "{}", tree );
+    }
+
+    #[test]
+    fn footer_totals() {
+        let root = scratch_tree( "footer", &[ ( "a", 5 ), ( "d/b", 10 ), ( "d/c", 2 ), ( "x.log", 3 ) ] );
+        let tree = report( &[ "-b", "--footer", "--aggr=4", "-x", "*.log", &root.to_string_lossy() ] );
+        let lines : Vec<&str> = tree.lines().collect();
+        // the directories count in the total size, and with the root in the number of directories
+        let bytes = fs::metadata( &root ).unwrap().len() + fs::metadata( root.join( "d" ) ).unwrap().len() + 17;
+        assert_eq!( lines[ lines.len() - 2 ], format!( "{} B in 3 files and 2 directories", bytes ) );
+        assert_eq!( lines[ lines.len() - 1 ], "1 aggregated, 1 excluded, 0 errors" );
+        assert!( !report( &[ "-b", "--aggr=4", &root.to_string_lossy() ] ).contains( " files and " ) );
+    }
+
+    #[test]
+    fn gitignore_rules() {
+        let root = scratch_tree( "gitignore", &[ ( "a.o", 100 ), ( "keep.o", 200 ), ( "build/x", 300 ), ( "secret", 400 ),
+                                                 ( "sub/local/y", 500 ), ( "sub/z.o", 600 ), ( "src/local", 700 ), ( "src/m.c", 800 ) ] );
+        fs::create_dir_all( root.join( ".git/info" ) ).unwrap();
+        fs::write( root.join( ".git/info/exclude" ), "secret\n" ).unwrap();
+        fs::write( root.join( ".gitignore" ), "*.o\nbuild/\n!keep.o\n" ).unwrap();
+        // anchored, so only sub/local and not src/local
+        fs::write( root.join( "sub/.gitignore" ), "/local\n" ).unwrap();
+        let expected = r#"       1726 B ROOT
+       1500 B ROOT/src
+        800 B ROOT/src/m.c
+        700 B ROOT/src/local
+        200 B ROOT/keep.o
+         19 B ROOT/.gitignore
+          7 B ROOT/sub
+          7 B ROOT/sub/.gitignore
+"#.replace( "ROOT", &root.to_string_lossy() );
+        assert_eq!( expected, report( &[ "--list", "-b", "-d", "3", "--type", "f", "--gitignore", &root.to_string_lossy() ] ) );
+        assert!( report( &[ "--list", "-b", "-d", "3", "--type", "f", &root.to_string_lossy() ] ).contains( "/sub/local/y\n" ) );
+    }
+
+    #[test]
+    fn exclude_caches() {
+        let root = scratch_tree( "caches", &[ ( "cache/a", 100 ), ( "bad/a", 200 ), ( "keep", 300 ) ] );
+        fs::write( root.join( "cache/CACHEDIR.TAG" ), "Signature: 8a477f597d28d172789f06886806bc55\n# a cache\n" ).unwrap();
+        // the tag only counts with the right signature
+        fs::write( root.join( "bad/CACHEDIR.TAG" ), "Signature: not a cache\n" ).unwrap();
+        let expected = r#"        523 B ROOT
+        300 B ROOT/keep
+        223 B ROOT/bad
+        200 B ROOT/bad/a
+         23 B ROOT/bad/CACHEDIR.TAG
+"#.replace( "ROOT", &root.to_string_lossy() );
+        assert_eq!( expected, report( &[ "--list", "-b", "--type", "f", "--exclude-caches", &root.to_string_lossy() ] ) );
+        assert!( report( &[ "--list", "-b", "--type", "f", &root.to_string_lossy() ] ).contains( "/cache\n" ) );
+    }
+
+    #[test]
+    fn pseudo_fs_skipped() {
+        let root = scratch_tree( "pseudo", &[ ( "virtual/a", 100 ), ( "disk/b", 200 ) ] );
+        let out  = scratch( "pseudo-out" );
+        let argv = |extra : &str| [ "dutree", "-o", &out.to_string_lossy(), "--list", "-b", "--type", "f", extra,
+                                    &root.to_string_lossy() ].iter().map( |arg| arg.to_string() ).collect();
+        let virt = fs::metadata( root.join( "virtual" ) ).unwrap();
+        #[cfg(target_os = "linux")]
+        let virt = ( virt.st_dev(), virt.st_ino() );
+        #[cfg(target_os = "macos")]
+        let virt = ( virt.dev() as u64, virt.ino() );
+
+        // a made up mount, what /proc/mounts lists can be anything
+        let _run = RUNS.lock().unwrap_or_else( |err| err.into_inner() );
+        let disk = "        200 B ROOT/disk\n        200 B ROOT/disk/b\n";
+        for ( extra, expected ) in [ ( "--no-progress", format!( "        200 B ROOT\n{}", disk ) ),
+                                   ( "--pseudo-fs", format!( "        300 B ROOT\n{}        100 B ROOT/virtual\n        100 B ROOT/virtual/a\n", disk ) ) ] {
+            match Config::from_args( argv( extra ) ) {
+                XOk(mut cfg) => {
+                    assert_eq!( extra == "--pseudo-fs", cfg.pseudo_fs.is_empty() );
+                    if !cfg.pseudo_fs.is_empty() { cfg.pseudo_fs.push( virt ) }
+                    run( &cfg );
+                },
+                _            => panic!( "invalid arguments {}", extra ),
+            }
+            assert_eq!( expected.replace( "ROOT", &root.to_string_lossy() ), fs::read_to_string( &out ).unwrap() );
+        }
+    }
+
+    #[test]
+    fn owner_filters() {
+        let root = scratch_tree( "owners", &[ ( "d/mine", 100 ), ( "d/theirs", 200 ) ] );
+        let ( uid, gid ) = unsafe { ( libc::getuid(), libc::getgid() ) };
+        // only root can give files away, others see all they own
+        let given = std::os::unix::fs::chown( root.join( "d/theirs" ), Some( 4242 ), Some( 4343 ) ).is_ok();
+        let list  = |filter : &str, id : String| report( &[ "--list", "-b", "--type", "f", filter, &id, &root.join( "d" ).to_string_lossy() ] )
+                                                   .replace( &*root.to_string_lossy(), "ROOT" );
+        let mine   = "        100 B ROOT/d\n        100 B ROOT/d/mine\n";
+        let theirs = "        200 B ROOT/d\n        200 B ROOT/d/theirs\n";
+        let both   = "        300 B ROOT/d\n        200 B ROOT/d/theirs\n        100 B ROOT/d/mine\n";
+        assert_eq!( if given { mine } else { both }, list( "--user" , uid.to_string() ) );
+        assert_eq!( if given { mine } else { both }, list( "--group", gid.to_string() ) );
+        if given {
+            assert_eq!( theirs, list( "--user" , "4242".to_string() ) );
+            assert_eq!( theirs, list( "--group", "4343".to_string() ) );
+            assert_eq!( "          0 B ROOT/d\n", list( "--user", "4343".to_string() ) );
+        }
+        assert!(  parses( &[ "--user", "root", "--group", "0", "test" ] ) );
+        assert!( !parses( &[ "--user", "no-such-user", "test" ] ) );
+        assert!( !parses( &[ "--group", "no-such-group", "test" ] ) );
+    }
+
+    #[test]
+    fn prune_empty() {
+        let root = scratch_tree( "prune", &[ ( "a/f", 100 ), ( "z/empty", 0 ) ] );
+        fs::create_dir_all( root.join( "e" ) ).unwrap();
+        fs::create_dir_all( root.join( "n/sub" ) ).unwrap();
+        let paths = |extra : &str| report( &[ "--format", "{path}", "--sort", "name", "-d", "2", extra, &root.to_string_lossy() ] )
+                                     .replace( &*root.to_string_lossy(), "ROOT" );
+        // an empty file still makes its directory hold something
+        assert_eq!( "ROOT\nROOT/a\nROOT/a/f\nROOT/z\nROOT/z/empty\n", paths( "--prune-empty" ) );
+        assert_eq!( "ROOT\nROOT/a\nROOT/a/f\nROOT/e\nROOT/n\nROOT/n/sub\nROOT/z\nROOT/z/empty\n", paths( "--no-progress" ) );
+        let tree = report( &[ "-d", "2", "--prune-empty", "--footer", &root.to_string_lossy() ] );
+        assert!( tree.contains( "\n|- a " ) && tree.contains( "\n`- z " ) && tree.contains( " in 2 files and 6 directories\n" ), "{}", tree );
+    }
+
+    #[test]
+    fn theme_colors_names() {
+        let names = |theme : &str| report( &[ "--theme", theme, "--color", "always", "--no-bar", "-d1", "test" ] )
+                                     .lines().filter_map( |line| line.split( "- " ).nth( 1 )?.split( ' ' ).next().map( String::from ) )
+                                     .collect::<Vec<_>>();
+        let dark = names( "dark" );
+        for name in &[ "\x1b[01;34mdir1\x1b[0m", "\x1b[01;36mlink\x1b[0m", "\x1b[01;31mlink_orphan\x1b[0m",
+                       "\x1b[01;32mfile.exe\x1b[0m", "\x1b[00;36mfile.mp3\x1b[0m", "file1" ] {
+            assert!( dark.iter().any( |n| n == name ), "{:?}", dark );
+        }
+        let mono = names( "mono" );
+        for name in &[ "\x1b[01mdir1\x1b[0m", "\x1b[03mlink\x1b[0m", "\x1b[07mlink_orphan\x1b[0m", "\x1b[04mfile.exe\x1b[0m",
+                       "file.mp3" ] {
+            assert!( mono.iter().any( |n| n == name ), "{:?}", mono );
+        }
+        assert!( names( "solarized" ).iter().any( |n| n == "\x1b[38;5;33mdir1\x1b[0m" ) );
+        assert!( !report( &[ "--theme", "dark", "test" ] ).contains( '\x1b' ) );
+        assert!( !parses( &[ "--theme", "neon", "test" ] ) );
+    }
+
+    #[test]
+    fn by_type_output() {
+        let root = scratch_tree( "by-type", &[ ( "a.MP4", 1000 ), ( "sub/b.mp4", 500 ), ( "c.tar.gz", 300 ), ( "README", 200 ),
+                                               ( "main.rs", 100 ), ( "f.jpg", 50 ), ( "g.pdf", 20 ), ( ".hidden.mp4", 10 ) ] );
+        // extensions go lowercase, the last one names the type
+        assert_eq!( r#"       1510 B         3 .mp4
+        300 B         1 .gz
+        200 B         1 (none)
+        100 B         1 .rs
+         50 B         1 .jpg
+         20 B         1 .pdf
+"#, report( &[ "--by-type", "-b", &root.to_string_lossy() ] ) );
+        // only what the filters let through
+        assert_eq!( r#"       1500 B         2 .mp4
+        300 B         1 .gz
+        200 B         1 (none)
+        100 B         1 .rs
+         20 B         1 .pdf
+"#, report( &[ "--by-type", "-b", "-H", "-x", "*.jpg", &root.to_string_lossy() ] ) );
+    }
+
+    #[test]
+    fn by_category_output() {
+        let root = scratch_tree( "by-category", &[ ( "a.MP4", 1000 ), ( "music/song.flac", 600 ), ( "c.tar.gz", 300 ), ( "README", 200 ),
+                                                   ( "main.rs", 100 ), ( "f.jpg", 50 ), ( "g.pdf", 20 ), ( "notes.md", 5 ) ] );
+        assert_eq!( r#"       1000 B         1 video
+        600 B         1 audio
+        300 B         1 archives
+        200 B         1 other
+        100 B         1 code
+         50 B         1 images
+         25 B         2 documents
+"#, report( &[ "--by-category", "-b", &root.to_string_lossy() ] ) );
+        assert_eq!( "        600 B         1 audio\n", report( &[ "--by-category", "-b", "--include", "music/**", &root.to_string_lossy() ] ) );
+    }
+
+    #[test]
+    fn age_histogram_output() {
+        let root = scratch_tree( "age-histogram", &[ ( "new", 1 ), ( "d/month", 20 ), ( "d/season", 300 ), ( "old", 4000 ), ( "older", 50000 ) ] );
+        const DAY : u64 = 24 * 60 * 60;
+        for &( file, days ) in &[ ( "new", 1 ), ( "d/month", 45 ), ( "d/season", 200 ), ( "old", 800 ), ( "older", 4000 ) ] {
+            let mtime = SystemTime::now() - Duration::from_secs( days * DAY );
+            fs::File::options().write( true ).open( root.join( file ) ).unwrap().set_modified( mtime ).unwrap();
+        }
+        let histogram = report( &[ "--age-histogram", "-b", &root.to_string_lossy() ] );
+        // the header sizes hold the directories too, and these depend on the filesystem
+        let rows : Vec<&str> = histogram.lines().filter( |line| !line.starts_with( "[ " ) ).collect();
+        assert_eq!( vec![ "          1 B         1 < 30d", "         20 B         1 30d-90d", "        300 B         1 90d-1y",
+                          "      54000 B         2 > 1y" ], rows );
+    }
+
+    #[test]
+    fn size_histogram_output() {
+        let root = scratch_tree( "size-histogram", &[ ( "a", 100 ), ( "b", 4095 ), ( "d/c", 4096 ), ( "d/e", 2 << 20 ), ( "f", 0 ) ] );
+        // sparse, so the test doesn't write it
+        fs::File::options().write( true ).open( root.join( "f" ) ).unwrap().set_len( 101 << 20 ).unwrap();
+        let histogram = report( &[ "--size-histogram", "-b", &root.to_string_lossy(), &root.join( "d" ).to_string_lossy() ] );
+        let headers : Vec<&str> = histogram.lines().filter_map( |line| line.strip_prefix( "[ " )?.split( ' ' ).next() ).collect();
+        assert_eq!( vec![ &*root.file_name().unwrap().to_string_lossy(), "d" ], headers );
+        let rows : Vec<&str> = histogram.lines().filter( |line| !line.starts_with( "[ " ) ).collect();
+        assert_eq!( vec![ "       4195 B         2 < 4K", "       4096 B         1 4K-1M", "    2097152 B         1 1M-100M",
+                          "  105906176 B         1 > 100M",
+                          "          0 B         0 < 4K", "       4096 B         1 4K-1M", "    2097152 B         1 1M-100M",
+                          "          0 B         0 > 100M" ], rows );
+    }
+
+    #[test]
+    fn total_of_paths() {
+        let paths = [ "test/dir1", "test/dir3" ];
+        let run = |args : &[&str]| report( &[ &[ "-b", "--type", "f" ], args, &paths[..] ].concat() );
+        assert_eq!( " 91%        2816 B test/dir1\n  8%         256 B test/dir3\n100%        3072 B total\n", run( &[ "-S", "--total" ] ) );
+        assert_eq!( "       2816 B test/dir1\n        256 B test/dir3\n", run( &[ "-S" ] ) );
+        let tree = run( &[ "-d1", "--total" ] );
+        assert!( tree.starts_with( "[ total 3072 B ]\n|- dir1 " ) && tree.contains( "  91%        2816 B\n" ), "{}", tree );
+        assert_eq!( tree.replacen( "total", "<collection>", 1 ), run( &[ "-d1" ] ) );
+        // a single path is its own total
+        assert_eq!( report( &[ "-b", "--total", "test/dir1" ] ), report( &[ "-b", "test/dir1" ] ) );
+    }
+
+    #[test]
+    fn collapse_chains() {
+        let root = scratch_tree( "collapse", &[ ( "a/b/c/f", 3 ), ( "x/y/f", 4 ), ( "x/z/g", 3 ) ] );
+        let tree = |depth : &str| report( &[ "--collapse", "--no-bar", "-b", "--type", "f", "-d", depth, &root.to_string_lossy() ] )
+                                    .replacen( &*root.file_name().unwrap().to_string_lossy(), "ROOT", 1 );
+        // a chain ends at a file, or where a directory holds more than one entry
+        assert_eq!( r#"[ ROOT 10 B ]
+|- x                                                          70%           7 B
+|  |- y                                                       57%           4 B
+|  |  `- f                                                   100%           4 B
+|  `- z                                                       42%           3 B
+|     `- g                                                   100%           3 B
+`- a/b/c                                                      30%           3 B
+   `- f                                                      100%           3 B
+"#, tree( "4" ) );
+        // only what the depth shows is merged
+        assert!( tree( "1" ).ends_with( "\n`- a                                                          30%           3 B\n" ) );
+        let list = report( &[ "--collapse", "--list", "-b", "--type", "f", "-d", "4", &root.to_string_lossy() ] );
+        assert!( list.contains( "/a/b/c\n" ) && !list.contains( "/a/b\n" ), "{}", list );
+    }
+
+    #[test]
+    fn sparse_files() {
+        let root = scratch_tree( "sparse", &[ ( "hole", 0 ), ( "full", 8192 ), ( "d/part", 3000 ), ( "d/small", 100 ) ] );
+        fs::File::options().write( true ).open( root.join( "hole" ) ).unwrap().set_len( 1 << 20 ).unwrap();
+        fs::File::options().write( true ).open( root.join( "d/part" ) ).unwrap().set_len( 100000 ).unwrap();
+        std::os::unix::fs::symlink( "full", root.join( "link" ) ).unwrap();
+        let tree = report( &[ "--sparse", "-b", "--no-bar", "--name-width", "40", "-d2", &root.to_string_lossy() ] );
+        // apparent size, then what is allocated
+        for line in &[ "\n|- hole (sparse, 0 B allocated)        90%     1048576 B     1048576 B           0 B\n",
+                       "\n|  |- part (sparse, 4096 B allocated)  95%      100000 B      100000 B        4096 B\n",
+                       "\n|  `- small                             0%         100 B         100 B        4096 B\n",
+                       "\n|- full                                 0%        8192 B        8192 B        8192 B\n",
+                       "\n`- link                                 0%           4 B           4 B           0 B\n" ] {
+            assert!( tree.contains( line ), "{}", tree );
+        }
+        // files kept inline allocate no blocks, a few missing bytes aren't holes
+        let mut inline = Entry { name : 0, path : NO_NAME, parent : 0, bytes : 1500, totals : Totals { size : 1500, count : 1, ..Totals::default() },
+                                 dev : 0, ino : 0, mode : 0o100644, uid : 0, gid : 0, target : NO_NAME, color : None,
+                                 aggregated : false, entries : None };
+        assert!( !inline.is_sparse() );
+        inline.totals.size = 1 << 20;
+        assert!( inline.is_sparse() );
+        assert!( !report( &[ "-b", "--no-bar", "--name-width", "40", &root.to_string_lossy() ] ).contains( "allocated" ) );
+    }
+
+    #[test]
+    fn empty_bars() {
+        assert_eq!( "|      |", fmt_bar( &[ 0, 0 ], 8, BarStyle::Ascii, None ) );
+        assert_eq!( "│      │", fmt_bar( &[ 0, 0, 0 ], 8, BarStyle::Blocks, Some( "32" ) ) );
+        assert_eq!( "|    ##|", fmt_bar( &[ 2, 1 ], 8, BarStyle::Ascii, None ) );
     }
 
     /*